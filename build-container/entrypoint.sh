@@ -18,14 +18,16 @@ if [ -n "${GPG_KEY_DATA-}" ]; then
     gpg --import-ownertrust --no-tty <<<"${GPG_KEY_ID}:5:"
 fi
 
-# Set up repository directory
+# Set up repository directory; the architecture comes from repo.architectures
+# in syspac.toml, passed in by the workflow
 REPO_ROOT="/repo"
+REPO_ARCH="${REPO_ARCH:-x86_64}"
 echo "Creating repository directory structure..."
-sudo mkdir -p "${REPO_ROOT}/x86_64"
+sudo mkdir -p "${REPO_ROOT}/${REPO_ARCH}"
 
 # Initialize repository if needed
 initialize_repo() {
-    local dir="${REPO_ROOT}/x86_64"
+    local dir="${REPO_ROOT}/${REPO_ARCH}"
     echo "Initializing repository at ${dir}..."
     cd "${dir}"
 
@@ -42,16 +44,18 @@ initialize_repo() {
 # packages can be preserved. Pruning and DB rebuild are now handled entirely
 # by the GitHub Actions workflow.
 download_repo_files() {
-    local dir="${REPO_ROOT}/x86_64"
+    local dir="${REPO_ROOT}/${REPO_ARCH}"
     cd "${dir}"
 
     echo "Checking for existing repository files..."
 
-    # Determine release tag (default to 'repo' if not provided)
+    # Release tag and repository name from syspac.toml, passed in by the
+    # workflow (`syspac config release.tag` / `repo.name`)
     local release_tag="${RELEASE_TAG:-repo}"
+    local repo_name="${REPO_NAME:-syspac}"
 
     # Download database files if they exist, but don't fail if they don't
-    for file in syspac.{db,files}{,.tar.gz}; do
+    for file in "${repo_name}".{db,files}{,.tar.gz}; do
         echo "Attempting to download ${file} from tag ${release_tag}..."
         curl -sSfL -o "${file}" \
              "https://github.com/${GITHUB_REPOSITORY}/releases/download/${release_tag}/${file}" || {
@@ -117,8 +121,8 @@ build_package() {
 
     # Move built packages to repository
     echo "Moving built packages to repository..."
-    sudo mkdir -p "${REPO_ROOT}/x86_64"
-    if ! sudo find . -name "*.pkg.tar.zst" -exec sudo mv -v {} "${REPO_ROOT}/x86_64/" \;; then
+    sudo mkdir -p "${REPO_ROOT}/${REPO_ARCH}"
+    if ! sudo find . -name "*.pkg.tar.zst" -exec sudo mv -v {} "${REPO_ROOT}/${REPO_ARCH}/" \;; then
         echo "ERROR: Failed to move built packages!"
         return 1
     fi
@@ -149,7 +153,7 @@ fi
 
 # NOTE: Repository database pruning and full rebuild (including signing) are now
 # handled entirely by the GitHub Actions workflow. The container only builds
-# packages into /repo/x86_64; it does not modify the pacman DB.
+# packages into /repo/<arch>; it does not modify the pacman DB.
 
 echo "============================================"
 echo "Build process completed successfully at $(date)"
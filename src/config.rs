@@ -0,0 +1,611 @@
+use anyhow::{Context, Result};
+use git2::{Repository, Tree};
+use globset::{Glob, GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Name of the repository configuration file at the repo root
+pub const CONFIG_FILE: &str = "syspac.toml";
+
+/// Repository configuration loaded from `syspac.toml`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The pacman repository built from the packages
+    pub repo: RepoConfig,
+    /// Where the built repository is published
+    pub release: ReleaseConfig,
+    /// Where packages are discovered
+    pub packages: PackagesConfig,
+    /// Files that should not trigger a rebuild when they change
+    pub ignore: IgnoreConfig,
+    /// Shared paths whose changes rebuild a set of packages
+    #[serde(rename = "trigger")]
+    pub triggers: Vec<TriggerConfig>,
+    /// Named package groups: package names (or globs) per group, on top of
+    /// the `groups=` of the PKGBUILDs
+    pub groups: BTreeMap<String, Vec<String>>,
+}
+
+impl Config {
+    /// Compiles the rules used by change detection
+    pub fn change_rules(&self) -> Result<ChangeRules> {
+        Ok(ChangeRules {
+            ignore: IgnoreRules::new(&self.ignore)?,
+            triggers: TriggerRules::new(&self.triggers)?,
+        })
+    }
+}
+
+/// The pacman repository built from the packages
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepoConfig {
+    /// Repository name, as in `<name>.db` and the `[name]` section of pacman.conf
+    pub name: String,
+    /// Architectures to build for, one directory each
+    pub architectures: Vec<String>,
+    /// GPG key id used to sign packages and the database
+    /// (defaults to the `GPG_KEY_ID` variable of the workflow)
+    pub signing_key: Option<String>,
+}
+
+impl Default for RepoConfig {
+    fn default() -> Self {
+        RepoConfig {
+            name: "syspac".to_string(),
+            architectures: vec!["x86_64".to_string()],
+            signing_key: None,
+        }
+    }
+}
+
+/// Where the built repository is published
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReleaseConfig {
+    /// Release tag the packages and database are uploaded to
+    pub tag: String,
+}
+
+impl Default for ReleaseConfig {
+    fn default() -> Self {
+        ReleaseConfig {
+            tag: "repo".to_string(),
+        }
+    }
+}
+
+/// Where packages are discovered
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PackagesConfig {
+    /// Globs, relative to the repository root, matching the directories
+    /// scanned for packages; `.` is the repository root itself
+    pub roots: Vec<String>,
+    /// Levels below a root searched for package directories
+    pub max_depth: usize,
+    /// Directories that never contain packages; globs relative to the
+    /// repository root, a pattern without a `/` matches the directory name
+    /// Hidden directories are always skipped.
+    pub exclude: Vec<String>,
+    /// Build settings of individual packages, keyed by package name
+    /// (a package can carry them in its own `syspac.toml` instead)
+    pub settings: BTreeMap<String, PackageSettings>,
+    /// The keys each `settings` section sets, as written; they override the
+    /// same keys of the package's own `syspac.toml`
+    #[serde(skip)]
+    pub settings_keys: BTreeMap<String, toml::Table>,
+}
+
+impl Default for PackagesConfig {
+    fn default() -> Self {
+        PackagesConfig {
+            roots: vec![".".to_string()],
+            max_depth: 2,
+            exclude: ["target", "node_modules", "build-container", "repo"]
+                .iter()
+                .map(|dir| dir.to_string())
+                .collect(),
+            settings: BTreeMap::new(),
+            settings_keys: BTreeMap::new(),
+        }
+    }
+}
+
+impl PackagesConfig {
+    /// Compiles the exclusion patterns
+    pub fn exclude_rules(&self) -> Result<GlobSet> {
+        build_globset(&self.exclude).context("Invalid package exclusion pattern")
+    }
+}
+
+/// Build settings of one package, from its `[packages.settings.<name>]`
+/// section or the `syspac.toml` in the package directory
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PackageSettings {
+    /// Never build the package, e.g. while it is broken upstream
+    pub skip_build: bool,
+    /// Architectures to build for (all of `repo.architectures` if empty)
+    pub architectures: Vec<String>,
+    /// Extra environment variables for the build
+    pub env: BTreeMap<String, String>,
+    /// Build timeout in minutes (the workflow default if unset)
+    pub timeout_minutes: Option<u64>,
+    /// People responsible for the package
+    pub maintainers: Vec<String>,
+    /// Where new upstream releases are looked for
+    pub upstream: Option<UpstreamConfig>,
+}
+
+/// Where to check for new upstream releases of a package
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamConfig {
+    /// GitHub repository (`owner/name`) whose releases are checked
+    pub github: Option<String>,
+    /// Git repository URL whose tags are checked
+    pub git: Option<String>,
+    /// Only tags matching this glob are considered, e.g. `v*`
+    pub tags: Option<String>,
+    /// Prefix stripped from a tag to get the pkgver, e.g. `v`
+    pub prefix: Option<String>,
+    /// Do not check this package for new releases
+    pub disabled: bool,
+}
+
+impl PackageSettings {
+    /// Checks the values serde cannot
+    pub fn validate(&self) -> Result<()> {
+        if self.timeout_minutes == Some(0) {
+            anyhow::bail!("timeout_minutes must be greater than 0");
+        }
+
+        for name in self.env.keys() {
+            let valid = !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                anyhow::bail!("Invalid environment variable name: {}", name);
+            }
+        }
+
+        if let Some(upstream) = &self.upstream {
+            if upstream.github.is_some() && upstream.git.is_some() {
+                anyhow::bail!("upstream: set either github or git, not both");
+            }
+            if let Some(github) = &upstream.github {
+                if github.split('/').filter(|part| !part.is_empty()).count() != 2 {
+                    anyhow::bail!("upstream.github must be owner/name: {}", github);
+                }
+            }
+            if let Some(tags) = &upstream.tags {
+                Glob::new(tags).context(format!("Invalid upstream tag pattern: {}", tags))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses the `syspac.toml` of a package directory
+pub fn parse_package_settings(content: &str) -> Result<PackageSettings> {
+    package_settings(toml::from_str(content)?)
+}
+
+/// Merges the settings of a package: the keys of its
+/// `[packages.settings.<name>]` section replace the same keys of its own
+/// `syspac.toml`, the others are kept
+pub fn merge_package_settings(
+    content: Option<&str>,
+    config: &PackagesConfig,
+    name: &str,
+) -> Result<PackageSettings> {
+    let mut table: toml::Table = match content {
+        Some(content) => toml::from_str(content)?,
+        None => toml::Table::new(),
+    };
+    if let Some(keys) = config.settings_keys.get(name) {
+        table.extend(keys.clone());
+    }
+    package_settings(table)
+}
+
+fn package_settings(table: toml::Table) -> Result<PackageSettings> {
+    let settings: PackageSettings = table.try_into()?;
+    settings.validate()?;
+    Ok(settings)
+}
+
+/// Ignore rules for change detection
+///
+/// Patterns are globs relative to the package directory. A pattern without
+/// a `/` also matches the file name at any depth, like in `.gitignore`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IgnoreConfig {
+    /// Patterns applied to every package
+    pub patterns: Vec<String>,
+    /// Additional patterns for individual packages, keyed by package name
+    pub packages: BTreeMap<String, Vec<String>>,
+}
+
+/// Maps changes under shared paths (outside or inside packages) to the
+/// packages they affect, e.g. the build container rebuilding everything
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerConfig {
+    /// Globs relative to the repository root
+    pub paths: Vec<String>,
+    /// Packages to rebuild when a matching path changes
+    pub packages: TriggerTargets,
+}
+
+/// The packages a trigger rebuilds: `"all"` or a list of package names
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum TriggerTargets {
+    All(AllPackages),
+    Packages(Vec<String>),
+}
+
+/// The `"all"` keyword in a trigger's package set
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AllPackages {
+    All,
+}
+
+impl TriggerTargets {
+    /// Checks whether the trigger rebuilds the named package
+    pub fn includes(&self, package: &str) -> bool {
+        match self {
+            TriggerTargets::All(_) => true,
+            TriggerTargets::Packages(names) => names.iter().any(|n| n == package),
+        }
+    }
+}
+
+/// Loads the configuration from the repository root
+/// A missing file yields the default configuration
+pub fn load_config(repo_path: &str) -> Result<Config> {
+    let path = Path::new(repo_path).join(CONFIG_FILE);
+
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+
+    let content = fs::read_to_string(&path)
+        .context(format!("Failed to read config at {}", path.display()))?;
+
+    parse_config(&content).context(format!("Invalid config at {}", path.display()))
+}
+
+/// Loads the configuration of a repository: from the working directory, or
+/// from the HEAD tree of a bare repository
+pub fn load_repo_config(repo_path: &str) -> Result<Config> {
+    let repo = Repository::open(repo_path)
+        .context(format!("Failed to open repository at {}", repo_path))?;
+
+    if !repo.is_bare() {
+        return load_config(repo_path);
+    }
+
+    let tree = repo.head().ok().and_then(|head| head.peel_to_tree().ok());
+    match tree {
+        Some(tree) => load_config_from_tree(&repo, &tree),
+        None => Ok(Config::default()),
+    }
+}
+
+/// Loads the configuration recorded in a git tree, e.g. in a bare repository
+/// A missing file yields the default configuration
+pub fn load_config_from_tree(repo: &Repository, tree: &Tree) -> Result<Config> {
+    let entry = match tree.get_name(CONFIG_FILE) {
+        Some(entry) => entry,
+        None => return Ok(Config::default()),
+    };
+
+    let blob = entry
+        .to_object(repo)?
+        .peel_to_blob()
+        .context(format!("Failed to read {} blob", CONFIG_FILE))?;
+
+    parse_config(&String::from_utf8_lossy(blob.content()))
+        .context(format!("Invalid config in {}", CONFIG_FILE))
+}
+
+/// Parses configuration from TOML content
+pub fn parse_config(content: &str) -> Result<Config> {
+    let mut config: Config = toml::from_str(content)?;
+
+    for (name, settings) in &config.packages.settings {
+        settings
+            .validate()
+            .context(format!("Invalid settings for package {}", name))?;
+    }
+
+    // Remember which keys were written, so unset ones do not override a
+    // package's own syspac.toml with defaults
+    let raw: toml::Table = toml::from_str(content)?;
+    if let Some(sections) = raw
+        .get("packages")
+        .and_then(|packages| packages.get("settings"))
+        .and_then(toml::Value::as_table)
+    {
+        for (name, section) in sections {
+            if let Some(section) = section.as_table() {
+                config
+                    .packages
+                    .settings_keys
+                    .insert(name.clone(), section.clone());
+            }
+        }
+    }
+
+    Ok(config)
+}
+
+/// Compiled ignore rules, ready to match changed files against
+#[derive(Debug, Clone)]
+pub struct IgnoreRules {
+    global: GlobSet,
+    packages: BTreeMap<String, GlobSet>,
+}
+
+impl IgnoreRules {
+    /// Compiles the ignore patterns from the configuration
+    pub fn new(config: &IgnoreConfig) -> Result<Self> {
+        let global = build_globset(&config.patterns)?;
+
+        let mut packages = BTreeMap::new();
+        for (name, patterns) in &config.packages {
+            let set = build_globset(patterns)
+                .context(format!("Invalid ignore pattern for package {}", name))?;
+            packages.insert(name.clone(), set);
+        }
+
+        Ok(IgnoreRules { global, packages })
+    }
+
+    /// Checks whether a file, relative to the package directory, is ignored
+    pub fn is_ignored(&self, package: &str, relative_path: &str) -> bool {
+        // The package path itself (e.g. a submodule gitlink) is never ignored
+        if relative_path.is_empty() {
+            return false;
+        }
+
+        let file_name = relative_path.rsplit('/').next().unwrap_or(relative_path);
+        let matches = |set: &GlobSet| set.is_match(relative_path) || set.is_match(file_name);
+
+        matches(&self.global) || self.packages.get(package).is_some_and(matches)
+    }
+}
+
+/// Compiled triggers, ready to match changed files against
+#[derive(Debug, Clone, Default)]
+pub struct TriggerRules {
+    triggers: Vec<(Vec<(String, GlobMatcher)>, TriggerTargets)>,
+}
+
+impl TriggerRules {
+    /// Compiles the trigger table from the configuration
+    pub fn new(triggers: &[TriggerConfig]) -> Result<Self> {
+        let mut compiled = Vec::new();
+
+        for trigger in triggers {
+            let mut matchers = Vec::new();
+            for pattern in &trigger.paths {
+                let glob = Glob::new(pattern.trim_start_matches('/'))
+                    .context(format!("Invalid trigger path: {}", pattern))?;
+                matchers.push((pattern.clone(), glob.compile_matcher()));
+            }
+            compiled.push((matchers, trigger.packages.clone()));
+        }
+
+        Ok(TriggerRules { triggers: compiled })
+    }
+
+    /// Returns the pattern and targets of every trigger matching a
+    /// repository-relative path
+    pub fn matches(&self, path: &str) -> Vec<(&str, &TriggerTargets)> {
+        self.triggers
+            .iter()
+            .filter_map(|(matchers, targets)| {
+                matchers
+                    .iter()
+                    .find(|(_, matcher)| matcher.is_match(path))
+                    .map(|(pattern, _)| (pattern.as_str(), targets))
+            })
+            .collect()
+    }
+}
+
+/// All compiled rules change detection applies to changed files
+#[derive(Debug, Clone)]
+pub struct ChangeRules {
+    pub ignore: IgnoreRules,
+    pub triggers: TriggerRules,
+}
+
+/// Builds a glob set, letting patterns without `/` match file names only
+pub fn build_globset(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+
+    for pattern in patterns {
+        let glob = if pattern.contains('/') {
+            Glob::new(pattern.trim_start_matches('/'))
+        } else {
+            // Only match the file name, never across directories
+            GlobBuilder::new(pattern).literal_separator(true).build()
+        }
+        .context(format!("Invalid glob pattern: {}", pattern))?;
+
+        builder.add(glob);
+    }
+
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config_empty() {
+        let config = parse_config("").unwrap();
+        assert!(config.ignore.patterns.is_empty());
+        assert!(config.ignore.packages.is_empty());
+    }
+
+    #[test]
+    fn test_ignore_rules() {
+        let config = parse_config(
+            r#"
+[ignore]
+patterns = ["README.md", ".nvchecker.toml", "docs/**"]
+
+[ignore.packages]
+niri = ["*.png"]
+"#,
+        )
+        .unwrap();
+        let rules = IgnoreRules::new(&config.ignore).unwrap();
+
+        assert!(rules.is_ignored("niri", "README.md"));
+        assert!(rules.is_ignored("niri", "contrib/README.md"));
+        assert!(rules.is_ignored("valent", ".nvchecker.toml"));
+        assert!(rules.is_ignored("valent", "docs/usage.txt"));
+        assert!(rules.is_ignored("niri", "screenshots/main.png"));
+
+        assert!(!rules.is_ignored("valent", "screenshots/main.png"));
+        assert!(!rules.is_ignored("niri", "PKGBUILD"));
+        assert!(!rules.is_ignored("niri", ""));
+    }
+
+    #[test]
+    fn test_trigger_rules() {
+        let config = parse_config(
+            r#"
+[[trigger]]
+paths = ["build-container/**"]
+packages = "all"
+
+[[trigger]]
+paths = ["patches/wayland/**", "patches/common.patch"]
+packages = ["niri", "valent"]
+"#,
+        )
+        .unwrap();
+        let rules = TriggerRules::new(&config.triggers).unwrap();
+
+        let matches = rules.matches("build-container/Dockerfile");
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].0, "build-container/**");
+        assert!(matches[0].1.includes("ly"));
+
+        let matches = rules.matches("patches/common.patch");
+        assert_eq!(matches[0].0, "patches/common.patch");
+        assert!(matches[0].1.includes("niri"));
+        assert!(!matches[0].1.includes("ly"));
+
+        assert!(rules.matches("README.md").is_empty());
+    }
+
+    #[test]
+    fn test_trigger_rejects_unknown_keyword() {
+        assert!(parse_config("[[trigger]]\npaths = [\"x\"]\npackages = \"some\"\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_config_defaults() {
+        let config = parse_config("[repo]\nsigning_key = \"ABCDEF\"\n").unwrap();
+        assert_eq!(config.repo.name, "syspac");
+        assert_eq!(config.repo.architectures, vec!["x86_64"]);
+        assert_eq!(config.repo.signing_key.as_deref(), Some("ABCDEF"));
+        assert_eq!(config.release.tag, "repo");
+        assert_eq!(config.packages.roots, vec!["."]);
+        assert!(config
+            .packages
+            .exclude
+            .contains(&"build-container".to_string()));
+    }
+
+    #[test]
+    fn test_parse_config_unknown_keys() {
+        let error = parse_config("[repo]\nnmae = \"x\"\n").unwrap_err();
+        assert!(error.to_string().contains("unknown field `nmae`"));
+        assert!(parse_config("[relase]\ntag = \"x\"\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_config_groups() {
+        let config = parse_config("[groups]\ndesktop = [\"niri\", \"valent\"]\n").unwrap();
+        assert_eq!(config.groups["desktop"], vec!["niri", "valent"]);
+        assert!(parse_config("").unwrap().groups.is_empty());
+    }
+
+    #[test]
+    fn test_package_settings() {
+        let config = parse_config(
+            r#"
+[packages.settings.niri]
+skip_build = true
+env = { RUSTFLAGS = "-C target-cpu=x86-64-v3" }
+timeout_minutes = 90
+maintainers = ["alice@example.com"]
+upstream = { github = "YaLTeR/niri", tags = "v*", prefix = "v" }
+"#,
+        )
+        .unwrap();
+        let niri = &config.packages.settings["niri"];
+        assert!(niri.skip_build);
+        assert_eq!(niri.env["RUSTFLAGS"], "-C target-cpu=x86-64-v3");
+        assert_eq!(niri.timeout_minutes, Some(90));
+        assert_eq!(
+            niri.upstream.as_ref().unwrap().github.as_deref(),
+            Some("YaLTeR/niri")
+        );
+
+        let settings = parse_package_settings("maintainers = [\"bob\"]\n").unwrap();
+        assert_eq!(settings.maintainers, vec!["bob"]);
+        assert!(!settings.skip_build);
+
+        assert!(parse_package_settings("timeout_minutes = 0").is_err());
+        assert!(parse_package_settings("env = { \"1X\" = \"y\" }").is_err());
+        assert!(parse_package_settings("upstream = { github = \"niri\" }").is_err());
+        assert!(parse_package_settings("upstream = { github = \"a/b\", git = \"c\" }").is_err());
+        assert!(parse_package_settings("skip = true").is_err());
+        assert!(parse_config("[packages.settings.niri]\ntimeout_minutes = 0").is_err());
+    }
+
+    #[test]
+    fn test_merge_package_settings() {
+        let config =
+            parse_config("[packages.settings.niri]\nskip_build = true\nenv = { A = \"root\" }\n")
+                .unwrap();
+        let own = "skip_build = false\ntimeout_minutes = 30\nenv = { B = \"own\" }\n";
+
+        // Keys of the root section win; the package's other keys are kept
+        let niri = merge_package_settings(Some(own), &config.packages, "niri").unwrap();
+        assert!(niri.skip_build);
+        assert_eq!(niri.timeout_minutes, Some(30));
+        assert_eq!(niri.env.keys().collect::<Vec<_>>(), vec!["A"]);
+
+        let other = merge_package_settings(Some(own), &config.packages, "other").unwrap();
+        assert!(!other.skip_build);
+        assert_eq!(other.env["B"], "own");
+
+        let root_only = merge_package_settings(None, &config.packages, "niri").unwrap();
+        assert_eq!(&root_only, &config.packages.settings["niri"]);
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let config = parse_config("[ignore]\npatterns = [\"[\"]\n").unwrap();
+        assert!(IgnoreRules::new(&config.ignore).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::package::Package;
+use crate::pkgbuild::{dependency_name, parse_arrays_str};
+
+/// Dependency arrays that make a package need a rebuild when a dependency changes
+const DEPENDENCY_ARRAYS: &[&str] = &["depends", "makedepends"];
+
+/// Dependency graph between the packages of the repository
+///
+/// Only edges between in-repo packages are kept; dependencies on packages
+/// from the distribution repositories are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    /// Package name -> names of in-repo packages that depend on it
+    dependents: BTreeMap<String, Vec<String>>,
+}
+
+impl DependencyGraph {
+    /// Builds the graph by parsing the dependency arrays of every PKGBUILD
+    ///
+    /// `read_pkgbuild` supplies the PKGBUILD content of a package, e.g. the
+    /// blob at the commit the packages were discovered in.
+    pub fn build(
+        packages: &[Package],
+        read_pkgbuild: impl Fn(&Package) -> Result<String>,
+    ) -> Result<Self> {
+        // Map every name a package provides (pkgname entries and provides) to
+        // the packages providing it; several may provide the same name
+        let mut providers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut dependencies: Vec<(String, Vec<String>)> = Vec::new();
+
+        let mut names = vec!["pkgname", "provides"];
+        names.extend_from_slice(DEPENDENCY_ARRAYS);
+
+        for package in packages {
+            let arrays = match read_pkgbuild(package)
+                .and_then(|content| parse_arrays_str(&content, &names))
+            {
+                Ok(arrays) => arrays,
+                Err(e) => {
+                    eprintln!("warning: skipping dependencies of {}: {}", package.name, e);
+                    continue;
+                }
+            };
+
+            let provided = arrays[0].iter().chain(&arrays[1]);
+            for provided in std::iter::once(&package.name).chain(provided) {
+                let entry = providers
+                    .entry(dependency_name(provided).to_string())
+                    .or_default();
+                if !entry.contains(&package.name) {
+                    entry.push(package.name.clone());
+                }
+            }
+
+            let deps = arrays[2..]
+                .iter()
+                .flatten()
+                .map(|dep| dependency_name(dep).to_string())
+                .collect();
+            dependencies.push((package.name.clone(), deps));
+        }
+
+        let mut graph = DependencyGraph::default();
+        for (name, deps) in dependencies {
+            for dep in deps {
+                for provider in providers.get(&dep).into_iter().flatten() {
+                    if *provider != name {
+                        graph.add_edge(provider, &name);
+                    }
+                }
+            }
+        }
+
+        Ok(graph)
+    }
+
+    /// Records that `dependent` depends on `dependency`
+    pub fn add_edge(&mut self, dependency: &str, dependent: &str) {
+        let entry = self.dependents.entry(dependency.to_string()).or_default();
+        if !entry.iter().any(|d| d == dependent) {
+            entry.push(dependent.to_string());
+            entry.sort();
+        }
+    }
+
+    /// Expands a set of changed packages to every package that transitively
+    /// depends on them
+    ///
+    /// Returns `(dependent, dependency)` pairs, where `dependency` is the
+    /// package that pulled the dependent in. Changed packages themselves are
+    /// never returned.
+    pub fn transitive_dependents(&self, changed: &[String]) -> Vec<(String, String)> {
+        let mut seen: Vec<String> = changed.to_vec();
+        let mut queue: VecDeque<String> = changed.iter().cloned().collect();
+        let mut result = Vec::new();
+
+        while let Some(name) = queue.pop_front() {
+            for dependent in self.dependents.get(&name).into_iter().flatten() {
+                if seen.contains(dependent) {
+                    continue;
+                }
+                seen.push(dependent.clone());
+                queue.push_back(dependent.clone());
+                result.push((dependent.clone(), name.clone()));
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitive_dependents() {
+        let mut graph = DependencyGraph::default();
+        graph.add_edge("libfoo", "foo-cli");
+        graph.add_edge("foo-cli", "foo-gui");
+        graph.add_edge("libfoo", "foo-gui");
+        graph.add_edge("libbar", "bar");
+
+        let result = graph.transitive_dependents(&["libfoo".to_string()]);
+        assert_eq!(
+            result,
+            vec![
+                ("foo-cli".to_string(), "libfoo".to_string()),
+                ("foo-gui".to_string(), "libfoo".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_transitive_dependents_skips_changed() {
+        let mut graph = DependencyGraph::default();
+        graph.add_edge("libfoo", "foo-cli");
+        graph.add_edge("foo-cli", "libfoo");
+
+        let changed = vec!["libfoo".to_string(), "foo-cli".to_string()];
+        assert!(graph.transitive_dependents(&changed).is_empty());
+    }
+
+    #[test]
+    fn test_build_keeps_every_provider() {
+        let package = |name: &str| Package {
+            name: name.to_string(),
+            path: name.to_string(),
+            pkgbuild_path: format!("{}/PKGBUILD", name),
+            submodule: None,
+            pkgbase: None,
+            pkgnames: Vec::new(),
+            groups: Vec::new(),
+            settings: Default::default(),
+        };
+        let packages = [package("libfoo"), package("libfoo-git"), package("app")];
+
+        // libfoo-git provides the name of a real package
+        let graph = DependencyGraph::build(&packages, |package| {
+            Ok(match package.name.as_str() {
+                "libfoo" => "pkgname=libfoo\n",
+                "libfoo-git" => "pkgname=libfoo-git\nprovides=('libfoo=1.1')\n",
+                _ => "pkgname=app\ndepends=('libfoo')\n",
+            }
+            .to_string())
+        })
+        .unwrap();
+
+        for changed in ["libfoo", "libfoo-git"] {
+            assert_eq!(
+                graph.transitive_dependents(&[changed.to_string()]),
+                vec![("app".to_string(), changed.to_string())]
+            );
+        }
+    }
+}
@@ -0,0 +1,101 @@
+/// A build directive embedded in a commit message
+///
+/// Supported forms:
+/// - `[rebuild: foo bar]` forces the listed packages to be rebuilt
+/// - `[rebuild-all]` forces every package to be rebuilt
+/// - `[skip-build: baz]` drops the listed packages from the result
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Directive {
+    Rebuild(Vec<String>),
+    RebuildAll,
+    SkipBuild(Vec<String>),
+}
+
+impl std::fmt::Display for Directive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Directive::Rebuild(names) => write!(f, "[rebuild: {}]", names.join(" ")),
+            Directive::RebuildAll => write!(f, "[rebuild-all]"),
+            Directive::SkipBuild(names) => write!(f, "[skip-build: {}]", names.join(" ")),
+        }
+    }
+}
+
+/// Parses every directive found in a commit message
+/// Bracketed text that is not a directive is ignored
+pub fn parse_directives(message: &str) -> Vec<Directive> {
+    let mut directives = Vec::new();
+    let mut rest = message;
+
+    while let Some(start) = rest.find('[') {
+        let after = &rest[start + 1..];
+        let end = match after.find(']') {
+            Some(end) => end,
+            None => break,
+        };
+
+        if let Some(directive) = parse_directive(&after[..end]) {
+            directives.push(directive);
+        }
+
+        rest = &after[end + 1..];
+    }
+
+    directives
+}
+
+/// Parses the text between brackets as a single directive
+fn parse_directive(text: &str) -> Option<Directive> {
+    let text = text.trim();
+
+    if text == "rebuild-all" {
+        return Some(Directive::RebuildAll);
+    }
+
+    let (keyword, args) = text.split_once(':')?;
+    let names: Vec<String> = args
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string())
+        .collect();
+
+    if names.is_empty() {
+        return None;
+    }
+
+    match keyword.trim() {
+        "rebuild" => Some(Directive::Rebuild(names)),
+        "skip-build" => Some(Directive::SkipBuild(names)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_directives() {
+        let message = "Bump libfoo [rebuild: foo-cli, foo-gui]\n\n[skip-build: docs] [rebuild-all]";
+        assert_eq!(
+            parse_directives(message),
+            vec![
+                Directive::Rebuild(vec!["foo-cli".to_string(), "foo-gui".to_string()]),
+                Directive::SkipBuild(vec!["docs".to_string()]),
+                Directive::RebuildAll,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_directives_ignores_other_brackets() {
+        assert!(parse_directives("[WIP] fix [ci skip] [rebuild:] [foo: bar]").is_empty());
+        assert!(parse_directives("unterminated [rebuild: foo").is_empty());
+    }
+
+    #[test]
+    fn test_directive_display() {
+        let directive = Directive::Rebuild(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(directive.to_string(), "[rebuild: a b]");
+    }
+}
@@ -0,0 +1,225 @@
+use anyhow::{Context, Result};
+use git2::{Oid, Repository};
+use serde::Deserialize;
+use std::fs;
+
+use crate::git::DetectOptions;
+
+/// The `before` SHA GitHub sends when a push creates a branch
+const ZERO_SHA: &str = "0000000000000000000000000000000000000000";
+
+/// The commit range described by a GitHub webhook payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventRange {
+    /// A push event
+    Push {
+        /// Previous branch tip (None when the push created the branch)
+        before: Option<String>,
+        /// New branch tip
+        after: String,
+        /// Whether the push rewrote history
+        forced: bool,
+        /// The repository's default branch, compared against for new branches
+        default_branch: Option<String>,
+    },
+    /// A pull_request event
+    PullRequest {
+        /// Tip of the branch the pull request targets
+        base: String,
+        /// Tip of the pull request branch
+        head: String,
+    },
+}
+
+/// Payload of a `pull_request` event (only the fields we need)
+#[derive(Debug, Deserialize)]
+struct PullRequestPayload {
+    pull_request: PullRequest,
+}
+
+#[derive(Debug, Deserialize)]
+struct PullRequest {
+    base: CommitRef,
+    head: CommitRef,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommitRef {
+    sha: String,
+}
+
+/// Payload of a `push` event (only the fields we need)
+#[derive(Debug, Deserialize)]
+struct PushPayload {
+    before: String,
+    after: String,
+    #[serde(default)]
+    forced: bool,
+    repository: Option<Repo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Repo {
+    default_branch: Option<String>,
+}
+
+/// Loads the range from an event payload file, e.g. `$GITHUB_EVENT_PATH`
+pub fn load_event(path: &str) -> Result<EventRange> {
+    let content =
+        fs::read_to_string(path).context(format!("Failed to read event payload at {}", path))?;
+
+    parse_event(&content).context(format!("Invalid event payload at {}", path))
+}
+
+/// Parses a push or pull_request payload
+pub fn parse_event(content: &str) -> Result<EventRange> {
+    let value: serde_json::Value = serde_json::from_str(content)?;
+
+    // pull_request "synchronize" payloads also carry before/after, so check them first
+    if value.get("pull_request").is_some() {
+        let payload: PullRequestPayload = serde_json::from_value(value)?;
+        return Ok(EventRange::PullRequest {
+            base: payload.pull_request.base.sha,
+            head: payload.pull_request.head.sha,
+        });
+    }
+
+    if value.get("before").is_some() {
+        let payload: PushPayload = serde_json::from_value(value)?;
+        return Ok(EventRange::Push {
+            before: Some(payload.before).filter(|sha| sha != ZERO_SHA),
+            after: payload.after,
+            forced: payload.forced,
+            default_branch: payload.repository.and_then(|r| r.default_branch),
+        });
+    }
+
+    anyhow::bail!("Unsupported event: expected a push or pull_request payload")
+}
+
+impl EventRange {
+    /// Sets the base and head of the detection options to this range
+    ///
+    /// A push diffs `before..after` exactly, even when forced (the trees are
+    /// compared, so `before` need not be an ancestor). A new branch, or a
+    /// forced push whose `before` was not fetched, is compared against its
+    /// merge-base with the default branch, or against the parents of `after`
+    /// if the default branch is not available. A pull request is compared
+    /// against its merge-base with the base branch, like the pull request
+    /// diff on GitHub.
+    pub fn apply(&self, repo_path: &str, options: &mut DetectOptions) -> Result<()> {
+        let repo = Repository::open(repo_path)
+            .context(format!("Failed to open repository at {}", repo_path))?;
+
+        match self {
+            EventRange::Push {
+                before,
+                after,
+                forced,
+                default_branch,
+            } => {
+                options.head_ref = Some(after.clone());
+                options.base_ref = before.clone();
+
+                // History rewritten by a forced push is usually not fetched
+                let rewritten = before.as_deref().filter(|sha| {
+                    *forced
+                        && Oid::from_str(sha)
+                            .and_then(|oid| repo.find_commit(oid))
+                            .is_err()
+                });
+                if let Some(sha) = rewritten {
+                    eprintln!(
+                        "warning: forced push replaced {}, which is not available; \
+                         comparing against the default branch",
+                        sha
+                    );
+                    options.base_ref = None;
+                }
+
+                if options.base_ref.is_none() {
+                    options.merge_base = default_branch
+                        .as_deref()
+                        .and_then(|branch| resolve_branch(&repo, branch));
+                }
+            }
+            EventRange::PullRequest { base, head } => {
+                options.head_ref = Some(head.clone());
+                options.merge_base = Some(base.clone());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Finds a branch as a remote-tracking branch of origin or a local branch
+fn resolve_branch(repo: &Repository, branch: &str) -> Option<String> {
+    [
+        format!("refs/remotes/origin/{}", branch),
+        format!("refs/heads/{}", branch),
+    ]
+    .into_iter()
+    .find(|name| repo.find_reference(name).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_push_event() {
+        let event = parse_event(
+            r#"{"ref": "refs/heads/main", "before": "aaa", "after": "bbb", "forced": true,
+                "repository": {"default_branch": "main"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            event,
+            EventRange::Push {
+                before: Some("aaa".to_string()),
+                after: "bbb".to_string(),
+                forced: true,
+                default_branch: Some("main".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_new_branch_push_event() {
+        let event =
+            parse_event(&format!(r#"{{"before": "{}", "after": "bbb"}}"#, ZERO_SHA)).unwrap();
+
+        assert!(matches!(
+            event,
+            EventRange::Push {
+                before: None,
+                forced: false,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_parse_pull_request_event() {
+        let event = parse_event(
+            r#"{"action": "synchronize", "before": "xxx", "after": "yyy",
+                "pull_request": {"base": {"sha": "aaa"}, "head": {"sha": "bbb"}}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            event,
+            EventRange::PullRequest {
+                base: "aaa".to_string(),
+                head: "bbb".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_unsupported_event() {
+        assert!(parse_event(r#"{"action": "created", "comment": {}}"#).is_err());
+    }
+}
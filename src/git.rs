@@ -1,137 +1,1626 @@
 use anyhow::{Context, Result};
-use git2::{DiffOptions, Oid, Repository};
-use std::collections::HashSet;
+use git2::{Commit, Diff, DiffOptions, FileMode, Index, ObjectType, Oid, Repository, Sort, Tree};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
 
-use crate::package::{find_all_packages, Package};
+use crate::config::{load_config, ChangeRules, Config, PackagesConfig};
+use crate::deps::DependencyGraph;
+use crate::directives::{parse_directives, Directive};
+use crate::package::{
+    direct_tree_package, find_all_packages, find_gitlink, find_packages_in_tree,
+    open_submodule_repo, read_gitmodules, validate_names, Package, PackageFilter,
+};
+use crate::pkgbuild;
 
-/// Detects packages that have changed between the base ref and HEAD
-pub fn detect_changed_packages(repo_path: &str, base_ref: Option<&str>) -> Result<Vec<String>> {
-    let repo = Repository::open(repo_path)
-        .context(format!("Failed to open repository at {}", repo_path))?;
+/// A single commit, summarised for reporting
+#[derive(Debug, Clone, Serialize)]
+pub struct CommitInfo {
+    pub id: String,
+    pub summary: String,
+    pub author: String,
+}
 
-    // Get all packages first
-    let all_packages = find_all_packages(repo_path)?;
+/// Describes how a submodule's pinned commit moved between two superproject commits
+#[derive(Debug, Clone, Serialize)]
+pub struct SubmoduleUpdate {
+    /// Package name of the submodule
+    pub name: String,
+    /// Relative path of the submodule from repo root
+    pub path: String,
+    /// Pinned commit at the base ref (None if the submodule was added)
+    pub old_commit: Option<String>,
+    /// Pinned commit at HEAD (None if the submodule was removed)
+    pub new_commit: Option<String>,
+    /// Upstream commits reachable from the new pin but not the old one
+    pub commits: Vec<CommitInfo>,
+    /// False when the submodule's object store could not provide the log
+    pub log_available: bool,
+}
 
-    // If no base ref provided, try to get HEAD^ (parent of current commit)
-    let base_ref = match base_ref {
-        Some(r) => r.to_string(),
-        None => {
-            // Try to get HEAD^
-            match get_head_parent(&repo) {
-                Ok(oid) => oid.to_string(),
-                Err(_) => {
-                    // First commit or no parent available - return all packages
-                    return Ok(all_packages.iter().map(|p| p.name.clone()).collect());
-                }
+/// Why a package was selected by change detection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ChangeReason {
+    /// Files under the package path changed
+    FilesChanged,
+    /// The submodule was pinned to a different commit
+    SubmoduleBumped {
+        old_commit: Option<String>,
+        new_commit: Option<String>,
+    },
+    /// The submodule checkout has uncommitted changes
+    SubmoduleDirty,
+    /// A shared path matching a configured trigger changed
+    Triggered { pattern: String, file: String },
+    /// A commit message in the range requested the rebuild
+    Directive { commit: String, directive: String },
+    /// An in-repo package this one depends on changed
+    DependencyChanged { dependency: String },
+    /// The package no longer exists at head
+    Removed,
+    /// There was no base commit to compare against (e.g. first commit)
+    NoBase,
+    /// The base commit was not available and `--missing-base all` was given
+    BaseUnavailable,
+    /// Every package was requested with `--all`
+    ForcedAll,
+}
+
+impl std::fmt::Display for ChangeReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChangeReason::FilesChanged => write!(f, "files changed"),
+            ChangeReason::SubmoduleBumped {
+                old_commit,
+                new_commit,
+            } => write!(
+                f,
+                "submodule bumped {}..{}",
+                short_sha(old_commit.as_deref()),
+                short_sha(new_commit.as_deref())
+            ),
+            ChangeReason::Triggered { pattern, file } => {
+                write!(f, "shared path {} matches trigger {}", file, pattern)
+            }
+            ChangeReason::Directive { commit, directive } => {
+                write!(
+                    f,
+                    "requested by {} in {}",
+                    directive,
+                    short_sha(Some(commit))
+                )
+            }
+            ChangeReason::DependencyChanged { dependency } => {
+                write!(f, "depends on changed package {}", dependency)
+            }
+            ChangeReason::SubmoduleDirty => write!(f, "submodule has uncommitted changes"),
+            ChangeReason::Removed => write!(f, "package removed"),
+            ChangeReason::NoBase => write!(f, "no base commit to compare against"),
+            ChangeReason::BaseUnavailable => write!(f, "base commit not available"),
+            ChangeReason::ForcedAll => write!(f, "forced by --all"),
+        }
+    }
+}
+
+/// How a selected package differs between the base and head
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeCategory {
+    /// The package does not exist at the base
+    Added,
+    /// The package exists at both base and head
+    #[default]
+    Modified,
+    /// The package exists at the base but not at head
+    Removed,
+    /// The package moved to a different path
+    Renamed,
+}
+
+impl ChangeCategory {
+    /// Categories reported when none are requested explicitly
+    pub const DEFAULT: &'static [ChangeCategory] = &[
+        ChangeCategory::Added,
+        ChangeCategory::Modified,
+        ChangeCategory::Renamed,
+    ];
+
+    /// All categories, in output order
+    pub const ALL: &'static [ChangeCategory] = &[
+        ChangeCategory::Added,
+        ChangeCategory::Modified,
+        ChangeCategory::Removed,
+        ChangeCategory::Renamed,
+    ];
+
+    /// The category name as used on the command line and in JSON
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeCategory::Added => "added",
+            ChangeCategory::Modified => "modified",
+            ChangeCategory::Removed => "removed",
+            ChangeCategory::Renamed => "renamed",
+        }
+    }
+}
+
+impl FromStr for ChangeCategory {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        ChangeCategory::ALL
+            .iter()
+            .find(|c| c.as_str() == s)
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "Unknown category: {} (expected added, modified, removed or renamed)",
+                    s
+                )
+            })
+    }
+}
+
+/// Name and path of a package at some point in history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackageLocation {
+    pub name: String,
+    pub path: String,
+}
+
+/// A package selected by change detection, with the evidence for it
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageChange {
+    /// Package name
+    pub name: String,
+    /// Relative path from repo root
+    pub path: String,
+    /// How the package differs between base and head
+    pub category: ChangeCategory,
+    /// Where a renamed package used to live
+    pub renamed_from: Option<PackageLocation>,
+    /// Why the package was selected
+    pub reasons: Vec<ChangeReason>,
+    /// Changed files under the package path
+    pub files: Vec<String>,
+    /// Commits in the range that touched those files (explain mode only)
+    pub commits: Vec<CommitInfo>,
+    /// `epoch:pkgver-pkgrel` at the base commit (explain and version-bump modes)
+    pub old_version: Option<String>,
+    /// `epoch:pkgver-pkgrel` at the head commit (explain and version-bump modes)
+    pub new_version: Option<String>,
+}
+
+impl PackageChange {
+    fn new(package: &Package) -> Self {
+        PackageChange {
+            name: package.name.clone(),
+            path: package.path.clone(),
+            category: ChangeCategory::default(),
+            renamed_from: None,
+            reasons: Vec::new(),
+            files: Vec::new(),
+            commits: Vec::new(),
+            old_version: None,
+            new_version: None,
+        }
+    }
+
+    fn add_reason(&mut self, reason: ChangeReason) {
+        if !self.reasons.contains(&reason) {
+            self.reasons.push(reason);
+        }
+    }
+
+    fn add_file(&mut self, file: &str) {
+        if !self.files.iter().any(|f| f == file) {
+            self.files.push(file.to_string());
+        }
+    }
+}
+
+/// A package dropped from the result by a `[skip-build: ...]` directive
+/// or its `skip_build` setting
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedPackage {
+    pub name: String,
+    /// Commit whose message carried the directive (None for the setting)
+    pub commit: Option<String>,
+}
+
+/// The result of change detection over a commit range
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangeReport {
+    /// Base commits that were compared against HEAD
+    pub bases: Vec<String>,
+    /// The head commit (None if no range could be resolved)
+    pub head: Option<String>,
+    /// Selected packages, sorted by name
+    pub packages: Vec<PackageChange>,
+    /// Packages that would have been selected but were skipped by a
+    /// directive or their settings
+    pub skipped: Vec<SkippedPackage>,
+    /// The fallback taken because a base commit was not available
+    pub fallback: Option<BaseFallback>,
+}
+
+/// Ref recording the last commit that was built, see [`mark_built`]
+pub const LAST_BUILD_REF: &str = "refs/syspac/last-build";
+
+/// What to do when a base commit is not available, e.g. in a shallow
+/// clone or after a force-push
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingBase {
+    /// Abort with an error
+    #[default]
+    Fail,
+    /// Fetch the missing history from the remote and try again
+    Deepen,
+    /// Compare against the last recorded build instead
+    LastBuild,
+    /// Select every package
+    All,
+}
+
+impl FromStr for MissingBase {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(MissingBase::Fail),
+            "deepen" => Ok(MissingBase::Deepen),
+            "last-build" => Ok(MissingBase::LastBuild),
+            "all" => Ok(MissingBase::All),
+            _ => Err(format!(
+                "Unknown missing-base strategy: {} (expected fail, deepen, last-build or all)",
+                s
+            )),
+        }
+    }
+}
+
+/// The fallback change detection took for a missing base commit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BaseFallback {
+    /// The missing history was fetched from a remote
+    Deepened { remote: String },
+    /// The last recorded build was used as the base
+    LastBuild { commit: String },
+    /// Every package was selected
+    All,
+}
+
+impl std::fmt::Display for BaseFallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BaseFallback::Deepened { remote } => {
+                write!(f, "fetched missing history from {}", remote)
+            }
+            BaseFallback::LastBuild { commit } => {
+                write!(
+                    f,
+                    "comparing against last build {}",
+                    short_sha(Some(commit))
+                )
             }
+            BaseFallback::All => write!(f, "selecting all packages"),
         }
+    }
+}
+
+/// How to choose base commits when HEAD is a merge commit and no base ref is given
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParentMode {
+    /// Compare against the first parent only (the branch that was merged into)
+    #[default]
+    First,
+    /// Compare against every parent and report the union of changes
+    All,
+}
+
+impl FromStr for ParentMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(ParentMode::First),
+            "all" => Ok(ParentMode::All),
+            _ => Err(format!(
+                "Unknown parent mode: {} (expected first or all)",
+                s
+            )),
+        }
+    }
+}
+
+/// Uncommitted state to compare HEAD against, for local development
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalChanges {
+    /// Changes staged in the index
+    Staged,
+    /// Everything in the working directory, staged or not (including untracked files)
+    Worktree,
+}
+
+/// Options selecting which commits change detection compares
+#[derive(Debug, Clone, Default)]
+pub struct DetectOptions {
+    /// Base commit/ref to compare against (defaults to the parents of the head)
+    pub base_ref: Option<String>,
+    /// Head commit/ref to compare (defaults to HEAD)
+    pub head_ref: Option<String>,
+    /// Compare against the merge-base of HEAD and this branch instead of a base ref
+    pub merge_base: Option<String>,
+    /// Which parents of a merge commit to compare against when no base is given
+    pub parents: ParentMode,
+    /// Compare HEAD against the index or working directory instead of a commit
+    pub local: Option<LocalChanges>,
+    /// Ignore `[rebuild: ...]`/`[skip-build: ...]` directives in commit messages
+    pub no_directives: bool,
+    /// Also select in-repo packages that transitively depend on changed ones
+    pub dependents: bool,
+    /// Select every package regardless of changes
+    pub all: bool,
+    /// Categories to report (defaults to added, modified and renamed)
+    pub categories: Option<Vec<ChangeCategory>>,
+    /// Attribute changes to commits and read old/new versions
+    pub explain: bool,
+    /// Only select packages whose version changed, ignoring other file churn
+    pub version_bumps: bool,
+    /// What to do when a base commit is not available
+    pub missing_base: MissingBase,
+    /// Remote to fetch from with [`MissingBase::Deepen`] (defaults to `origin`)
+    pub remote: Option<String>,
+    /// Only report packages selected by this filter
+    pub filter: PackageFilter,
+}
+
+/// The commits being compared: one or more bases against a single head
+struct CommitRange {
+    bases: Vec<Oid>,
+    head: Oid,
+}
+
+/// Detects packages that have changed between the base commit(s) and HEAD
+pub fn detect_changed_packages(
+    repo_path: &str,
+    config: &Config,
+    options: &DetectOptions,
+) -> Result<ChangeReport> {
+    let mut repo = Repository::open(repo_path)
+        .context(format!("Failed to open repository at {}", repo_path))?;
+
+    let rules = config.change_rules()?;
+    let packages_config = &config.packages;
+
+    let mut changes: BTreeMap<String, PackageChange> = BTreeMap::new();
+    let (range, fallback) = match options.local {
+        Some(_) => (None, None),
+        // A full rebuild does not depend on being able to compare commits
+        None if options.all => (resolve_range(&repo, options).ok().flatten(), None),
+        None => resolve_range_or_fallback(&repo, options)?,
     };
+    if let Some(BaseFallback::Deepened { .. }) = fallback {
+        repo = Repository::open(repo_path)?;
+    }
 
-    // Parse the base reference
-    let base_object = repo
-        .revparse_single(&base_ref)
-        .context(format!("Failed to parse base ref: {}", base_ref))?;
-    let base_commit = base_object
-        .peel_to_commit()
-        .context("Failed to peel base ref to commit")?;
+    // Packages as of the head commit, so no checkout is needed; local
+    // changes are compared against the working directory
+    let head_tree = match (&range, options.local) {
+        (Some(range), None) => Some(repo.find_commit(range.head)?.tree()?),
+        _ => None,
+    };
+    let all_packages = match &head_tree {
+        Some(head_tree) => find_packages_in_tree(&repo, head_tree, packages_config)?,
+        None => find_all_packages(repo_path, packages_config)?,
+    };
+    validate_names(&all_packages)?;
+    let selector = options.filter.compile(config, &all_packages)?;
 
-    // Get HEAD commit
-    let head = repo.head().context("Failed to get HEAD")?;
-    let head_commit = head
-        .peel_to_commit()
-        .context("Failed to peel HEAD to commit")?;
+    match (&range, options.local) {
+        (_, Some(local)) => {
+            let diff = diff_local_changes(&repo, local)?;
+            find_changed_packages_in_diff(&repo, &diff, &all_packages, &rules, &mut changes);
+        }
+        (Some(range), None) => {
+            // Find changed packages against every base and merge the results
+            for base_oid in &range.bases {
+                find_changed_packages_between_commits(
+                    &repo,
+                    base_oid,
+                    &range.head,
+                    &all_packages,
+                    &rules,
+                    &mut changes,
+                )?;
+            }
+        }
+        (None, None) if !options.all => {
+            // First commit, no parent available or an unavailable base - return all packages
+            let reason = match fallback {
+                Some(BaseFallback::All) => ChangeReason::BaseUnavailable,
+                _ => ChangeReason::NoBase,
+            };
+            for package in &all_packages {
+                change_entry(&mut changes, package).add_reason(reason.clone());
+            }
+        }
+        (None, None) => {}
+    }
+
+    let mut skipped = Vec::new();
+    if let (Some(range), false) = (&range, options.no_directives) {
+        skipped = apply_directives(&repo, range, &all_packages, &mut changes)?;
+    }
+
+    if options.version_bumps {
+        retain_version_bumps(
+            &repo,
+            range.as_ref(),
+            options.local,
+            packages_config,
+            &mut changes,
+        )?;
+    }
 
-    // Find changed packages
-    let changed = find_changed_packages_between_commits(
+    if options.dependents {
+        // Dependencies are parsed from the same PKGBUILDs the packages were
+        // found in: blobs of the head commit, or the checkout for local changes
+        let graph = DependencyGraph::build(&all_packages, |package| match &head_tree {
+            Some(head_tree) => read_pkgbuild_at(&repo, head_tree, &package.path)
+                .ok_or_else(|| anyhow::anyhow!("PKGBUILD not found in the head commit")),
+            None => fs::read_to_string(&package.pkgbuild_path).context(format!(
+                "Failed to read PKGBUILD at {}",
+                package.pkgbuild_path
+            )),
+        })?;
+        let changed: Vec<String> = changes.keys().cloned().collect();
+
+        for (dependent, dependency) in graph.transitive_dependents(&changed) {
+            if let Some(package) = all_packages.iter().find(|p| p.name == dependent) {
+                change_entry(&mut changes, package)
+                    .add_reason(ChangeReason::DependencyChanged { dependency });
+            }
+        }
+    }
+
+    if options.all {
+        // An explicit --all overrides skip directives from commit messages
+        skipped.clear();
+        for package in &all_packages {
+            change_entry(&mut changes, package).add_reason(ChangeReason::ForcedAll);
+        }
+    }
+
+    // Unlike directives, the skip_build setting also applies to --all
+    for package in all_packages.iter().filter(|p| p.settings.skip_build) {
+        if changes.contains_key(&package.name) && !skipped.iter().any(|s| s.name == package.name) {
+            skipped.push(SkippedPackage {
+                name: package.name.clone(),
+                commit: None,
+            });
+        }
+    }
+
+    for skip in &skipped {
+        changes.remove(&skip.name);
+    }
+
+    let mut removed = categorize_changes(
         &repo,
-        &base_commit.id(),
-        &head_commit.id(),
+        range.as_ref(),
+        options.local,
+        packages_config,
         &all_packages,
+        &mut changes,
     )?;
 
-    Ok(changed)
+    if options.explain {
+        for changes in [&mut changes, &mut removed] {
+            if let Some(local) = options.local {
+                read_local_versions(&repo, local, changes)?;
+            } else if let Some(range) = &range {
+                attribute_commits(&repo, range, changes)?;
+                read_versions(&repo, range, packages_config, changes)?;
+            }
+        }
+    }
+
+    let categories = options
+        .categories
+        .as_deref()
+        .unwrap_or(ChangeCategory::DEFAULT);
+
+    let mut packages: Vec<PackageChange> = changes
+        .into_values()
+        .chain(removed.into_values())
+        .filter(|change| categories.contains(&change.category))
+        .filter(|change| selector.matches(&change_package(&all_packages, change)))
+        .collect();
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(ChangeReport {
+        bases: range
+            .as_ref()
+            .map(|r| r.bases.iter().map(|oid| oid.to_string()).collect())
+            .unwrap_or_default(),
+        head: range.as_ref().map(|r| r.head.to_string()),
+        packages,
+        skipped,
+        fallback,
+    })
+}
+
+/// Reports how submodule package pins moved between the base commit(s) and
+/// head of a change report, including the upstream commits each bump pulls in
+///
+/// Uses the range [`detect_changed_packages`] resolved, so a missing base is
+/// only handled (and a shallow clone only deepened) once.
+pub fn find_submodule_updates(
+    repo_path: &str,
+    config: &Config,
+    options: &DetectOptions,
+    report: &ChangeReport,
+) -> Result<Vec<SubmoduleUpdate>> {
+    let repo = Repository::open(repo_path)
+        .context(format!("Failed to open repository at {}", repo_path))?;
+
+    let range = match &report.head {
+        Some(head) => CommitRange {
+            bases: report
+                .bases
+                .iter()
+                .map(|base| Oid::from_str(base))
+                .collect::<Result<_, _>>()?,
+            head: Oid::from_str(head)?,
+        },
+        None => return Ok(Vec::new()),
+    };
+
+    let head_tree = repo.find_commit(range.head)?.tree()?;
+    let all_packages = find_packages_in_tree(&repo, &head_tree, &config.packages)?;
+    let selector = options.filter.compile(config, &all_packages)?;
+    let mut updates: Vec<SubmoduleUpdate> = Vec::new();
+
+    for base_oid in &range.bases {
+        let base_tree = repo.find_commit(*base_oid)?.tree()?;
+        let diff = repo.diff_tree_to_tree(Some(&base_tree), Some(&head_tree), None)?;
+        // Packages of submodules removed since the base, found on demand
+        let mut base_packages: Option<Vec<Package>> = None;
+
+        for delta in diff.deltas() {
+            let old_file = delta.old_file();
+            let new_file = delta.new_file();
+
+            // Only gitlink entries describe a submodule pin
+            if old_file.mode() != FileMode::Commit && new_file.mode() != FileMode::Commit {
+                continue;
+            }
+
+            let path = match new_file.path().or(old_file.path()) {
+                Some(p) => p.to_string_lossy().to_string(),
+                None => continue,
+            };
+
+            // With several bases, report each submodule once (against the first base)
+            if updates.iter().any(|u| u.path == path) {
+                continue;
+            }
+
+            let holds_submodule = |packages: &[Package]| {
+                packages
+                    .iter()
+                    .filter_map(|p| p.submodule.as_ref())
+                    .any(|s| s.path == path)
+            };
+            let packages = if holds_submodule(&all_packages) {
+                &all_packages
+            } else {
+                if base_packages.is_none() {
+                    base_packages =
+                        Some(find_packages_in_tree(&repo, &base_tree, &config.packages)?);
+                }
+                base_packages.as_deref().unwrap_or_default()
+            };
+            let submodule = match packages
+                .iter()
+                .filter_map(|p| p.submodule.as_ref())
+                .find(|s| s.path == path)
+            {
+                Some(s) => s,
+                None => continue,
+            };
+
+            let selected = packages
+                .iter()
+                .filter(|p| p.submodule.as_ref() == Some(submodule))
+                .any(|p| selector.matches(p));
+            if !selected {
+                continue;
+            }
+
+            let old_commit = (old_file.mode() == FileMode::Commit).then(|| old_file.id());
+            let new_commit = (new_file.mode() == FileMode::Commit).then(|| new_file.id());
+
+            let log =
+                new_commit.and_then(|new| submodule_log(&repo, &submodule.name, new, old_commit));
+
+            updates.push(SubmoduleUpdate {
+                name: submodule.name.clone(),
+                path,
+                old_commit: old_commit.map(|oid| oid.to_string()),
+                new_commit: new_commit.map(|oid| oid.to_string()),
+                // A removed submodule has no log to show
+                log_available: log.is_some() || new_commit.is_none(),
+                commits: log.unwrap_or_default(),
+            });
+        }
+    }
+
+    updates.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(updates)
+}
+
+/// Reads the version of each package from its PKGBUILD blob at a revision
+pub fn read_versions_at(
+    repo_path: &str,
+    rev: &str,
+    packages: &[Package],
+) -> Result<Vec<Option<pkgbuild::PackageVersion>>> {
+    let repo = Repository::open(repo_path)
+        .context(format!("Failed to open repository at {}", repo_path))?;
+
+    let tree = repo
+        .revparse_single(rev)
+        .context(format!("Failed to parse ref: {}", rev))?
+        .peel_to_tree()
+        .context(format!("Failed to peel {} to a tree", rev))?;
+
+    Ok(packages
+        .iter()
+        .map(|package| {
+            read_pkgbuild_at(&repo, &tree, &package.path)
+                .and_then(|content| pkgbuild::parse_version_str(&content).ok())
+        })
+        .collect())
 }
 
-/// Gets the parent commit of HEAD
-fn get_head_parent(repo: &Repository) -> Result<Oid> {
-    let head = repo.head().context("Failed to get HEAD")?;
-    let head_commit = head
+/// Records a commit as the last built state, for `--missing-base last-build`
+pub fn mark_built(repo_path: &str, rev: &str) -> Result<String> {
+    let repo = Repository::open(repo_path)
+        .context(format!("Failed to open repository at {}", repo_path))?;
+
+    let commit = repo
+        .revparse_single(rev)
+        .context(format!("Failed to parse ref: {}", rev))?
         .peel_to_commit()
-        .context("Failed to peel HEAD to commit")?;
+        .context(format!("Failed to peel {} to a commit", rev))?;
+
+    repo.reference(LAST_BUILD_REF, commit.id(), true, "syspac: mark built")
+        .context(format!("Failed to update {}", LAST_BUILD_REF))?;
+
+    Ok(commit.id().to_string())
+}
+
+/// Resolves the range to compare, applying the missing-base strategy when
+/// a base commit is not available
+///
+/// Returns no range with [`BaseFallback::All`]. The fallback is reported on
+/// stderr as well as returned.
+fn resolve_range_or_fallback(
+    repo: &Repository,
+    options: &DetectOptions,
+) -> Result<(Option<CommitRange>, Option<BaseFallback>)> {
+    let error = match resolve_range(repo, options) {
+        Ok(range) => return Ok((range, None)),
+        Err(e) => e,
+    };
+
+    let (range, fallback) = match options.missing_base {
+        MissingBase::Fail => {
+            return Err(error.context(
+                "Base commit not available (use --missing-base deepen, last-build or all)",
+            ))
+        }
+        MissingBase::Deepen => {
+            let remote = options.remote.as_deref().unwrap_or("origin");
+            fetch_history(repo, remote)?;
+
+            // Reopen, so the shallow boundary cached by libgit2 is dropped
+            let repo = Repository::open(repo.path())?;
+            let range = resolve_range(&repo, options).context(format!(
+                "Base commit still missing after fetching from {}",
+                remote
+            ))?;
+            let fallback = BaseFallback::Deepened {
+                remote: remote.to_string(),
+            };
+            (range, fallback)
+        }
+        MissingBase::LastBuild => {
+            let last_build = repo
+                .find_reference(LAST_BUILD_REF)
+                .and_then(|reference| reference.peel_to_commit())
+                .context(format!(
+                    "{:#}; no last build recorded in {}",
+                    error, LAST_BUILD_REF
+                ))?;
+            let head = resolve_head(repo, options)?;
+
+            let range = CommitRange {
+                bases: vec![last_build.id()],
+                head: head.id(),
+            };
+            let fallback = BaseFallback::LastBuild {
+                commit: last_build.id().to_string(),
+            };
+            (Some(range), fallback)
+        }
+        MissingBase::All => (None, BaseFallback::All),
+    };
+
+    eprintln!("warning: {:#}; {}", error, fallback);
+    Ok((range, Some(fallback)))
+}
 
-    if head_commit.parent_count() == 0 {
-        anyhow::bail!("HEAD has no parent (first commit)");
+/// Fetches the missing history from a remote, unshallowing a shallow clone
+///
+/// Runs `git fetch`: libgit2 cannot deepen a shallow clone over every
+/// transport (e.g. not over local paths).
+fn fetch_history(repo: &Repository, remote_name: &str) -> Result<()> {
+    repo.find_remote(remote_name)
+        .context(format!("Failed to find remote {}", remote_name))?;
+
+    let mut args = vec!["fetch", "--quiet"];
+    if repo.is_shallow() {
+        args.push("--unshallow");
+    }
+    args.push(remote_name);
+
+    let output = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.path())
+        .args(&args)
+        .output()
+        .context("Failed to execute git fetch")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to fetch from {}: {}",
+            remote_name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+/// Resolves the base commit(s) and head commit to compare
+/// Returns None when no base is given and HEAD has no parent
+fn resolve_range(repo: &Repository, options: &DetectOptions) -> Result<Option<CommitRange>> {
+    let head_commit = resolve_head(repo, options)?;
+
+    let bases = if let Some(target) = &options.merge_base {
+        // Compare against the point where HEAD forked from the target branch
+        let target_commit = repo
+            .revparse_single(target)
+            .context(format!("Failed to parse merge-base target: {}", target))?
+            .peel_to_commit()
+            .context("Failed to peel merge-base target to commit")?;
+
+        let merge_base = repo
+            .merge_base(head_commit.id(), target_commit.id())
+            .context(format!("No merge-base between the head and {}", target))?;
+
+        vec![merge_base]
+    } else if let Some(base_ref) = &options.base_ref {
+        // Parse the base reference
+        let base_object = repo
+            .revparse_single(base_ref)
+            .context(format!("Failed to parse base ref: {}", base_ref))?;
+        let base_commit = base_object
+            .peel_to_commit()
+            .context("Failed to peel base ref to commit")?;
+
+        vec![base_commit.id()]
+    } else {
+        // No base ref provided, use the parent(s) of HEAD
+        let parents = get_head_parents(&head_commit, options.parents);
+        if parents.is_empty() && repo.is_shallow() {
+            // The parents were cut off by the clone, this is not the first commit
+            anyhow::bail!("HEAD has no parent in this shallow clone");
+        }
+        if parents.is_empty() {
+            return Ok(None);
+        }
+        parents
+    };
+
+    // Make sure every base is actually present (e.g. not cut off by a shallow clone)
+    for base_oid in &bases {
+        repo.find_commit(*base_oid)
+            .context(format!("Base commit {} is not available", base_oid))?;
     }
 
-    Ok(head_commit.parent_id(0)?)
+    Ok(Some(CommitRange {
+        bases,
+        head: head_commit.id(),
+    }))
+}
+
+/// Resolves the head commit to compare: the head ref, or HEAD
+fn resolve_head<'a>(repo: &'a Repository, options: &DetectOptions) -> Result<Commit<'a>> {
+    match &options.head_ref {
+        Some(head_ref) => repo
+            .revparse_single(head_ref)
+            .context(format!("Failed to parse head ref: {}", head_ref))?
+            .peel_to_commit()
+            .context("Failed to peel head ref to commit"),
+        None => repo
+            .head()
+            .context("Failed to get HEAD")?
+            .peel_to_commit()
+            .context("Failed to peel HEAD to commit"),
+    }
+}
+
+/// Gets the parent commits of HEAD selected by the parent mode
+/// Returns an empty list for the first commit
+fn get_head_parents(head_commit: &Commit, mode: ParentMode) -> Vec<Oid> {
+    match mode {
+        ParentMode::First => head_commit.parent_ids().take(1).collect(),
+        ParentMode::All => head_commit.parent_ids().collect(),
+    }
+}
+
+/// Lists the commits in `old..new` from a submodule's own history
+/// Returns None if the submodule store is missing or lacks either commit
+fn submodule_log(
+    repo: &Repository,
+    name: &str,
+    new: Oid,
+    old: Option<Oid>,
+) -> Option<Vec<CommitInfo>> {
+    let sub_repo = open_submodule_repo(repo, name)?;
+
+    let mut revwalk = sub_repo.revwalk().ok()?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME).ok()?;
+    revwalk.push(new).ok()?;
+    if let Some(old) = old {
+        revwalk.hide(old).ok()?;
+    }
+
+    let mut commits = Vec::new();
+    for oid in revwalk {
+        let commit = sub_repo.find_commit(oid.ok()?).ok()?;
+        commits.push(CommitInfo {
+            id: commit.id().to_string(),
+            summary: commit.summary().unwrap_or("").to_string(),
+            author: commit.author().name().unwrap_or("unknown").to_string(),
+        });
+    }
+
+    Some(commits)
+}
+
+/// Classifies selected packages as added, modified or renamed relative to
+/// the base, and returns the packages that no longer exist at head
+///
+/// The package sets are read from the base and head trees, so packages
+/// deleted since the base are found even though they are gone from disk.
+fn categorize_changes(
+    repo: &Repository,
+    range: Option<&CommitRange>,
+    local: Option<LocalChanges>,
+    packages_config: &PackagesConfig,
+    all_packages: &[Package],
+    changes: &mut BTreeMap<String, PackageChange>,
+) -> Result<BTreeMap<String, PackageChange>> {
+    let mut removed_changes = BTreeMap::new();
+
+    // The state head is compared to: the first base, or HEAD for local changes
+    let base_tree = match (range, local) {
+        (_, Some(_)) => repo.head().ok().and_then(|head| head.peel_to_tree().ok()),
+        (Some(range), None) => Some(repo.find_commit(range.bases[0])?.tree()?),
+        (None, None) => None,
+    };
+
+    let base_tree = match base_tree {
+        Some(tree) => tree,
+        None => {
+            // Without a base, every package is new
+            for change in changes.values_mut() {
+                change.category = ChangeCategory::Added;
+            }
+            return Ok(removed_changes);
+        }
+    };
+
+    let head_tree = match (range, local) {
+        (Some(range), None) => Some(repo.find_commit(range.head)?.tree()?),
+        _ => None,
+    };
+
+    let base_packages = find_packages_in_tree(repo, &base_tree, packages_config)?;
+    let head_packages = match &head_tree {
+        Some(tree) => find_packages_in_tree(repo, tree, packages_config)?,
+        None => all_packages.to_vec(),
+    };
+
+    let removed: Vec<&Package> = base_packages
+        .iter()
+        .filter(|b| !head_packages.iter().any(|h| h.path == b.path))
+        .collect();
+    let added: Vec<&Package> = head_packages
+        .iter()
+        .filter(|h| !base_packages.iter().any(|b| b.path == h.path))
+        .collect();
+
+    // A removed package that reappears at another path is a rename; match
+    // by name first so identical PKGBUILDs cannot steal a moved package
+    let mut renames: Vec<(&Package, &Package)> = Vec::new();
+    for by_content in [false, true] {
+        for from in &removed {
+            if renames.iter().any(|(f, _)| f.path == from.path) {
+                continue;
+            }
+
+            let to = added.iter().find(|to| {
+                !renames.iter().any(|(_, t)| t.path == to.path)
+                    && if by_content {
+                        is_same_content(repo, &base_tree, head_tree.as_ref(), from, to)
+                    } else {
+                        from.name == to.name
+                    }
+            });
+            if let Some(to) = to {
+                renames.push((from, to));
+            }
+        }
+    }
+
+    for change in changes.values_mut() {
+        if let Some((from, _)) = renames.iter().find(|(_, to)| to.path == change.path) {
+            change.category = ChangeCategory::Renamed;
+            change.renamed_from = Some(PackageLocation {
+                name: from.name.clone(),
+                path: from.path.clone(),
+            });
+        } else if added.iter().any(|a| a.path == change.path) {
+            change.category = ChangeCategory::Added;
+        } else {
+            change.category = ChangeCategory::Modified;
+        }
+    }
+
+    let gone: Vec<Package> = removed
+        .into_iter()
+        .filter(|r| !renames.iter().any(|(from, _)| from.path == r.path))
+        .cloned()
+        .collect();
+
+    if !gone.is_empty() {
+        // Record the deleted files of removed packages, without ignore rules
+        let rules = Config::default().change_rules()?;
+        let diff = match local {
+            Some(local) => diff_local_changes(repo, local)?,
+            None => repo.diff_tree_to_tree(Some(&base_tree), head_tree.as_ref(), None)?,
+        };
+        find_changed_packages_in_diff(repo, &diff, &gone, &rules, &mut removed_changes);
+
+        for package in &gone {
+            let entry = change_entry(&mut removed_changes, package);
+            entry.category = ChangeCategory::Removed;
+            entry.reasons = vec![ChangeReason::Removed];
+        }
+    }
+
+    Ok(removed_changes)
+}
+
+/// Checks whether a package removed at the base has the same content as
+/// one added at head: same submodule pin or identical PKGBUILD
+fn is_same_content(
+    repo: &Repository,
+    base_tree: &Tree,
+    head_tree: Option<&Tree>,
+    from: &Package,
+    to: &Package,
+) -> bool {
+    if from.is_submodule() != to.is_submodule() {
+        return false;
+    }
+
+    let from_id = package_content_id(repo, Some(base_tree), from);
+    let to_id = package_content_id(repo, head_tree, to);
+
+    from_id.is_some() && from_id == to_id
+}
+
+/// Identifies a package's content: the gitlink commit for submodule packages,
+/// the PKGBUILD blob otherwise
+/// Without a tree, the working directory is used
+fn package_content_id(repo: &Repository, tree: Option<&Tree>, package: &Package) -> Option<Oid> {
+    match (tree, &package.submodule) {
+        (Some(tree), Some(submodule)) => tree
+            .get_path(Path::new(&submodule.path))
+            .ok()
+            .map(|e| e.id()),
+        (Some(tree), None) => tree
+            .get_path(&Path::new(&package.path).join("PKGBUILD"))
+            .ok()
+            .map(|e| e.id()),
+        (None, Some(submodule)) => repo.find_submodule(&submodule.name).ok()?.workdir_id(),
+        (None, None) => {
+            let pkgbuild = repo.workdir()?.join(&package.path).join("PKGBUILD");
+            Oid::hash_file(ObjectType::Blob, pkgbuild).ok()
+        }
+    }
+}
+
+/// Applies the build directives found in the commit messages of the range
+///
+/// Rebuild directives add packages to the result; skip directives are
+/// returned so they can be applied once the result is otherwise complete.
+fn apply_directives(
+    repo: &Repository,
+    range: &CommitRange,
+    packages: &[Package],
+    changes: &mut BTreeMap<String, PackageChange>,
+) -> Result<Vec<SkippedPackage>> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME | Sort::REVERSE)?;
+    revwalk.push(range.head)?;
+    for base_oid in &range.bases {
+        revwalk.hide(*base_oid)?;
+    }
+
+    let mut skipped: Vec<SkippedPackage> = Vec::new();
+
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        let message = commit.message().unwrap_or("");
+
+        for directive in parse_directives(message) {
+            let reason = ChangeReason::Directive {
+                commit: commit.id().to_string(),
+                directive: directive.to_string(),
+            };
+
+            match &directive {
+                Directive::RebuildAll => {
+                    for package in packages {
+                        change_entry(changes, package).add_reason(reason.clone());
+                    }
+                }
+                Directive::Rebuild(names) => {
+                    for name in names {
+                        match packages.iter().find(|p| &p.name == name) {
+                            Some(package) => {
+                                change_entry(changes, package).add_reason(reason.clone())
+                            }
+                            None => eprintln!(
+                                "warning: {} in {} names unknown package {}",
+                                directive,
+                                short_sha(Some(&commit.id().to_string())),
+                                name
+                            ),
+                        }
+                    }
+                }
+                Directive::SkipBuild(names) => {
+                    for name in names {
+                        if !skipped.iter().any(|s| &s.name == name) {
+                            skipped.push(SkippedPackage {
+                                name: name.clone(),
+                                commit: Some(commit.id().to_string()),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(skipped)
 }
 
 /// Finds packages that have changed between two commits
+/// and records the changed files on their entries
 fn find_changed_packages_between_commits(
     repo: &Repository,
     base_oid: &Oid,
     head_oid: &Oid,
     packages: &[Package],
-) -> Result<Vec<String>> {
+    rules: &ChangeRules,
+    changes: &mut BTreeMap<String, PackageChange>,
+) -> Result<()> {
     let base_commit = repo.find_commit(*base_oid)?;
     let head_commit = repo.find_commit(*head_oid)?;
 
     let base_tree = base_commit.tree()?;
     let head_tree = head_commit.tree()?;
 
-    let mut changed_packages = HashSet::new();
-
     // Create diff between the two trees
     let diff = repo.diff_tree_to_tree(Some(&base_tree), Some(&head_tree), None)?;
 
+    find_changed_packages_in_diff(repo, &diff, packages, rules, changes);
+
+    Ok(())
+}
+
+/// Maps the deltas of a diff to packages and records the changed files on
+/// their entries
+fn find_changed_packages_in_diff(
+    repo: &Repository,
+    diff: &Diff,
+    packages: &[Package],
+    rules: &ChangeRules,
+    changes: &mut BTreeMap<String, PackageChange>,
+) {
     // Check each delta (changed file) to see which package it belongs to
     for delta in diff.deltas() {
+        let old_file = delta.old_file();
+        let new_file = delta.new_file();
+
         // Consider both old and new paths so we correctly detect renames and deletions
         let mut candidate_paths = Vec::new();
 
-        if let Some(path) = delta.new_file().path() {
+        if let Some(path) = new_file.path() {
             candidate_paths.push(path.to_string_lossy().to_string());
         }
 
-        if let Some(path) = delta.old_file().path() {
+        if let Some(path) = old_file.path() {
             candidate_paths.push(path.to_string_lossy().to_string());
         }
 
         for path_str in candidate_paths {
-            // Check if this path belongs to any package
-            for package in packages {
-                if path_str.starts_with(&package.path) {
-                    changed_packages.insert(package.name.clone());
-                    break;
+            // Shared paths rebuild the packages their triggers name
+            for (pattern, targets) in rules.triggers.matches(&path_str) {
+                for package in packages.iter().filter(|p| targets.includes(&p.name)) {
+                    change_entry(changes, package).add_reason(ChangeReason::Triggered {
+                        pattern: pattern.to_string(),
+                        file: path_str.clone(),
+                    });
                 }
             }
+
+            let is_gitlink =
+                old_file.mode() == FileMode::Commit || new_file.mode() == FileMode::Commit;
+
+            // Check which packages this path belongs to
+            // (a submodule pin belongs to every package in the submodule)
+            for package in packages.iter().filter(|p| is_in_package(&path_str, p)) {
+                let is_pin = is_gitlink
+                    && package
+                        .submodule
+                        .as_ref()
+                        .is_some_and(|s| s.path == path_str);
+
+                // Files matching the ignore rules never mark a package as changed
+                let relative = if is_pin {
+                    ""
+                } else {
+                    relative_to_package(&path_str, package)
+                };
+                if rules.ignore.is_ignored(&package.name, relative) {
+                    continue;
+                }
+
+                // A pin movement only changes the packages of a collection it touched
+                if is_pin
+                    && old_file.id() != new_file.id()
+                    && !pin_moves_package(repo, package, old_file.id(), new_file.id())
+                {
+                    continue;
+                }
+
+                let reason = if is_pin && old_file.id() == new_file.id() {
+                    ChangeReason::SubmoduleDirty
+                } else if is_pin {
+                    ChangeReason::SubmoduleBumped {
+                        old_commit: (old_file.mode() == FileMode::Commit)
+                            .then(|| old_file.id().to_string()),
+                        new_commit: (new_file.mode() == FileMode::Commit)
+                            .then(|| new_file.id().to_string()),
+                    }
+                } else {
+                    ChangeReason::FilesChanged
+                };
+
+                let entry = change_entry(changes, package);
+                entry.add_file(&path_str);
+                entry.add_reason(reason);
+            }
         }
     }
+}
 
-    let mut result: Vec<String> = changed_packages.into_iter().collect();
-    result.sort();
-    Ok(result)
+/// Checks whether a repository-relative path lies inside a package,
+/// or is the gitlink of the submodule holding it
+fn is_in_package(path: &str, package: &Package) -> bool {
+    path == package.path
+        || path
+            .strip_prefix(&package.path)
+            .is_some_and(|rest| rest.starts_with('/'))
+        || package.submodule.as_ref().is_some_and(|s| s.path == path)
 }
 
-/// Checks if a path has changes between two commits
-pub fn has_path_changed(repo_path: &str, path: &str, base_ref: &str) -> Result<bool> {
-    let repo = Repository::open(repo_path)?;
+/// Checks whether moving a submodule pin from `old` to `new` changes the
+/// directory of a package inside the submodule
+/// Packages at the submodule root, or whose commits are not available
+/// locally, always count as changed
+fn pin_moves_package(repo: &Repository, package: &Package, old: Oid, new: Oid) -> bool {
+    let Some(submodule) = &package.submodule else {
+        return true;
+    };
+    let subdir = package.path[submodule.path.len()..].trim_start_matches('/');
+    if subdir.is_empty() {
+        return true;
+    }
+    let Some(sub_repo) = open_submodule_repo(repo, &submodule.name) else {
+        return true;
+    };
 
-    let base_object = repo.revparse_single(base_ref)?;
-    let base_commit = base_object.peel_to_commit()?;
+    let dir_id = |commit: Oid| {
+        let tree = sub_repo.find_commit(commit).ok()?.tree().ok()?;
+        tree.get_path(Path::new(subdir))
+            .ok()
+            .map(|entry| entry.id())
+    };
+    match (dir_id(old), dir_id(new)) {
+        (Some(old_id), Some(new_id)) => old_id != new_id,
+        _ => true,
+    }
+}
 
-    let head = repo.head()?;
-    let head_commit = head.peel_to_commit()?;
+/// Returns the part of a package file path relative to the package directory
+fn relative_to_package<'a>(path: &'a str, package: &Package) -> &'a str {
+    path.strip_prefix(&package.path)
+        .unwrap_or(path)
+        .trim_start_matches('/')
+}
 
-    let base_tree = base_commit.tree()?;
-    let head_tree = head_commit.tree()?;
+/// The package a change is about; a removed package is no longer
+/// discovered, so only its name and path are known
+fn change_package(all_packages: &[Package], change: &PackageChange) -> Package {
+    all_packages
+        .iter()
+        .find(|p| p.name == change.name)
+        .cloned()
+        .unwrap_or_else(|| Package {
+            name: change.name.clone(),
+            ..direct_tree_package(&change.path)
+        })
+}
+
+/// Gets the change entry for a package, creating it if needed
+fn change_entry<'a>(
+    changes: &'a mut BTreeMap<String, PackageChange>,
+    package: &Package,
+) -> &'a mut PackageChange {
+    changes
+        .entry(package.name.clone())
+        .or_insert_with(|| PackageChange::new(package))
+}
+
+/// Walks the commits in the range and records, for each changed package,
+/// the commits that touched its changed files
+fn attribute_commits(
+    repo: &Repository,
+    range: &CommitRange,
+    changes: &mut BTreeMap<String, PackageChange>,
+) -> Result<()> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+    revwalk.push(range.head)?;
+    for base_oid in &range.bases {
+        revwalk.hide(*base_oid)?;
+    }
+
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+
+        // Merge commits only repeat changes made by the commits they merge
+        if commit.parent_count() > 1 {
+            continue;
+        }
+
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+        let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+
+        let touched: Vec<String> = diff
+            .deltas()
+            .flat_map(|delta| [delta.old_file().path(), delta.new_file().path()])
+            .flatten()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+
+        for change in changes.values_mut() {
+            if touched.iter().any(|path| change.files.contains(path)) {
+                change.commits.push(CommitInfo {
+                    id: commit.id().to_string(),
+                    summary: commit.summary().unwrap_or("").to_string(),
+                    author: commit.author().name().unwrap_or("unknown").to_string(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads `epoch:pkgver-pkgrel` from the PKGBUILD blobs at the first base
+/// and at head
+/// A package that moved is read at its old location in the base
+fn read_versions(
+    repo: &Repository,
+    range: &CommitRange,
+    packages_config: &PackagesConfig,
+    changes: &mut BTreeMap<String, PackageChange>,
+) -> Result<()> {
+    let head_tree = repo.find_commit(range.head)?.tree()?;
+    let base_tree = match range.bases.first() {
+        Some(oid) => Some(repo.find_commit(*oid)?.tree()?),
+        None => None,
+    };
+    let base_packages = match &base_tree {
+        Some(tree) => find_packages_in_tree(repo, tree, packages_config)?,
+        None => Vec::new(),
+    };
+
+    let version_at = |tree: &Tree, path: &str| {
+        read_pkgbuild_at(repo, tree, path)
+            .and_then(|content| pkgbuild::parse_version_str(&content).ok())
+            .map(|version| version.to_string())
+    };
+
+    for change in changes.values_mut() {
+        let base_location = change.renamed_from.clone().or_else(|| {
+            base_packages
+                .iter()
+                .find(|p| p.name == change.name)
+                .map(|p| PackageLocation {
+                    name: p.name.clone(),
+                    path: p.path.clone(),
+                })
+        });
+
+        change.old_version = match (&base_tree, base_location) {
+            (Some(tree), Some(location)) => version_at(tree, &location.path),
+            _ => None,
+        };
+        change.new_version = version_at(&head_tree, &change.path);
+    }
+
+    Ok(())
+}
+
+/// Drops packages whose `epoch:pkgver-pkgrel` is the same at base and head
+///
+/// Packages requested by a commit directive are kept, as are packages whose
+/// version cannot be read at head (e.g. a pkgver computed at build time).
+fn retain_version_bumps(
+    repo: &Repository,
+    range: Option<&CommitRange>,
+    local: Option<LocalChanges>,
+    packages_config: &PackagesConfig,
+    changes: &mut BTreeMap<String, PackageChange>,
+) -> Result<()> {
+    match (range, local) {
+        (_, Some(local)) => read_local_versions(repo, local, changes)?,
+        (Some(range), None) => read_versions(repo, range, packages_config, changes)?,
+        // Without a base every package is new
+        (None, None) => return Ok(()),
+    }
+
+    changes.retain(|name, change| {
+        if change.new_version.is_none() {
+            eprintln!("warning: cannot read the version of {}, keeping it", name);
+            return true;
+        }
+
+        change.old_version != change.new_version
+            || change
+                .reasons
+                .iter()
+                .any(|reason| matches!(reason, ChangeReason::Directive { .. }))
+    });
+
+    Ok(())
+}
+
+/// Diffs the HEAD tree against the index or the working directory
+/// Submodules with moved or dirty checkouts show up as modified gitlinks
+fn diff_local_changes(repo: &Repository, local: LocalChanges) -> Result<Diff<'_>> {
+    // An unborn HEAD (no commits yet) compares against the empty tree
+    let head_tree = repo.head().ok().and_then(|head| head.peel_to_tree().ok());
 
     let mut diff_opts = DiffOptions::new();
-    diff_opts.pathspec(path);
+    diff_opts.ignore_submodules(false);
+
+    let diff = match local {
+        LocalChanges::Staged => {
+            repo.diff_tree_to_index(head_tree.as_ref(), None, Some(&mut diff_opts))?
+        }
+        LocalChanges::Worktree => {
+            diff_opts
+                .include_untracked(true)
+                .recurse_untracked_dirs(true);
+            repo.diff_tree_to_workdir_with_index(head_tree.as_ref(), Some(&mut diff_opts))?
+        }
+    };
+
+    Ok(diff)
+}
 
+/// Reads `epoch:pkgver-pkgrel` from the PKGBUILD at HEAD and from the index or
+/// working directory
+fn read_local_versions(
+    repo: &Repository,
+    local: LocalChanges,
+    changes: &mut BTreeMap<String, PackageChange>,
+) -> Result<()> {
+    let head_tree = repo.head().ok().and_then(|head| head.peel_to_tree().ok());
+    let index = repo.index()?;
+    let workdir = repo
+        .workdir()
+        .context("Repository has no working directory")?;
+
+    let parse = |content: String| {
+        pkgbuild::parse_version_str(&content)
+            .ok()
+            .map(|version| version.to_string())
+    };
+
+    for change in changes.values_mut() {
+        change.old_version = head_tree
+            .as_ref()
+            .and_then(|tree| read_pkgbuild_at(repo, tree, &change.path))
+            .and_then(parse);
+
+        let content = match local {
+            LocalChanges::Staged => read_pkgbuild_from_index(repo, &index, change),
+            LocalChanges::Worktree => {
+                fs::read_to_string(workdir.join(&change.path).join("PKGBUILD")).ok()
+            }
+        };
+        change.new_version = content.and_then(parse);
+    }
+
+    Ok(())
+}
+
+/// Reads the staged PKGBUILD of a package from the index
+/// For submodules the PKGBUILD is read at the staged gitlink commit
+fn read_pkgbuild_from_index(
+    repo: &Repository,
+    index: &Index,
+    change: &PackageChange,
+) -> Option<String> {
+    // The staged gitlink of the submodule holding the package, if any
+    let mut prefix = PathBuf::new();
+    for component in Path::new(&change.path).components() {
+        prefix.push(component);
+        match index.get_path(&prefix, 0) {
+            Some(entry) if entry.mode == u32::from(FileMode::Commit) => {
+                let subdir = Path::new(&change.path).strip_prefix(&prefix).ok()?;
+                return read_submodule_pkgbuild(
+                    repo,
+                    &prefix.to_string_lossy(),
+                    entry.id,
+                    &subdir.to_string_lossy(),
+                );
+            }
+            _ => continue,
+        }
+    }
+
+    let entry = index.get_path(&Path::new(&change.path).join("PKGBUILD"), 0)?;
+    let blob = repo.find_blob(entry.id).ok()?;
+    Some(String::from_utf8_lossy(blob.content()).to_string())
+}
+
+/// Reads the PKGBUILD of a package as it was in the given tree
+/// For submodule packages the PKGBUILD is read from the submodule's object
+/// store at the pinned commit
+fn read_pkgbuild_at(repo: &Repository, tree: &Tree, path: &str) -> Option<String> {
+    if let Some((gitlink, commit)) = find_gitlink(tree, path) {
+        let name = read_gitmodules(repo, tree)
+            .ok()?
+            .into_iter()
+            .find(|module| module.path == gitlink)
+            .map_or_else(|| gitlink.clone(), |module| module.name);
+        let subdir = path[gitlink.len()..].trim_start_matches('/');
+        return read_submodule_pkgbuild(repo, &name, commit, subdir);
+    }
+
+    let pkgbuild_path = format!("{}/PKGBUILD", path);
+    let blob = tree
+        .get_path(Path::new(&pkgbuild_path))
+        .ok()?
+        .to_object(repo)
+        .ok()?
+        .peel_to_blob()
+        .ok()?;
+
+    Some(String::from_utf8_lossy(blob.content()).to_string())
+}
+
+/// Reads the PKGBUILD in a directory of a submodule at the given commit
+/// (an empty directory is the submodule root)
+fn read_submodule_pkgbuild(
+    repo: &Repository,
+    name: &str,
+    commit: Oid,
+    subdir: &str,
+) -> Option<String> {
+    let sub_repo = open_submodule_repo(repo, name)?;
+    let sub_tree = sub_repo.find_commit(commit).ok()?.tree().ok()?;
+    let blob = sub_tree
+        .get_path(&Path::new(subdir).join("PKGBUILD"))
+        .ok()?
+        .to_object(&sub_repo)
+        .ok()?
+        .peel_to_blob()
+        .ok()?;
+
+    Some(String::from_utf8_lossy(blob.content()).to_string())
+}
+
+/// Shortens a commit SHA for display
+pub fn short_sha(sha: Option<&str>) -> String {
+    match sha {
+        Some(sha) => sha.chars().take(7).collect(),
+        None => "(none)".to_string(),
+    }
+}
+
+/// Checks if a path has changes between a base ref and HEAD
+/// If the path is a package, its changes are counted like `detect-changes`
+/// counts them: files matching its ignore rules do not count.
+#[allow(dead_code)]
+pub fn has_path_changed(repo_path: &str, path: &str, base_ref: &str) -> Result<bool> {
+    let repo = Repository::open(repo_path)
+        .context(format!("Failed to open repository at {}", repo_path))?;
+
+    let base_tree = repo
+        .revparse_single(base_ref)
+        .context(format!("Failed to parse base ref: {}", base_ref))?
+        .peel_to_commit()?
+        .tree()?;
+    let head_tree = repo.head()?.peel_to_commit()?.tree()?;
+
+    let path = path.trim_end_matches('/');
+    let mut diff_opts = DiffOptions::new();
+    diff_opts.pathspec(path);
     let diff = repo.diff_tree_to_tree(Some(&base_tree), Some(&head_tree), Some(&mut diff_opts))?;
 
-    Ok(diff.deltas().len() > 0)
+    let config = load_config(repo_path)?;
+    let Some(package) = find_all_packages(repo_path, &config.packages)?
+        .into_iter()
+        .find(|p| p.path == path)
+    else {
+        return Ok(diff.deltas().len() > 0);
+    };
+
+    let mut changes = BTreeMap::new();
+    find_changed_packages_in_diff(
+        &repo,
+        &diff,
+        &[package],
+        &config.change_rules()?,
+        &mut changes,
+    );
+    Ok(!changes.is_empty())
 }
 
 #[cfg(test)]
@@ -140,10 +1629,63 @@ mod tests {
 
     #[test]
     fn test_detect_changes_invalid_repo() {
-        let result = detect_changed_packages("/nonexistent/path", None);
+        let result = detect_changed_packages(
+            "/nonexistent/path",
+            &Config::default(),
+            &DetectOptions::default(),
+        );
         assert!(result.is_err());
     }
 
-    // Additional tests would require setting up test git repositories
-    // Consider using tempdir and git2 to create test fixtures
+    #[test]
+    fn test_has_path_changed_honors_ignore_rules() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let repo_path = dir.path().to_str().unwrap();
+        let commit = |message: &str| {
+            let mut index = repo.index().unwrap();
+            index
+                .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+                .unwrap();
+            index.write().unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let signature = git2::Signature::now("Test User", "test@example.com").unwrap();
+            let parent = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+            let parents: Vec<&Commit> = parent.iter().collect();
+            repo.commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents,
+            )
+            .unwrap()
+            .to_string()
+        };
+
+        fs::write(
+            dir.path().join("syspac.toml"),
+            "[ignore]\npatterns = [\"*.md\"]\n",
+        )
+        .unwrap();
+        fs::create_dir(dir.path().join("foo")).unwrap();
+        fs::write(
+            dir.path().join("foo").join("PKGBUILD"),
+            "pkgname=foo\npkgver=1.0.0\npkgrel=1\n",
+        )
+        .unwrap();
+        let base = commit("Add foo");
+
+        fs::write(dir.path().join("foo").join("README.md"), "docs").unwrap();
+        commit("Document foo");
+        assert!(!has_path_changed(repo_path, "foo", &base).unwrap());
+        // Outside a package every file counts
+        assert!(has_path_changed(repo_path, "foo/README.md", &base).unwrap());
+
+        fs::write(dir.path().join("foo").join("fix.patch"), "patch").unwrap();
+        commit("Patch foo");
+        assert!(has_path_changed(repo_path, "foo/", &base).unwrap());
+        assert!(!has_path_changed(repo_path, "bar", &base).unwrap());
+    }
 }
@@ -1,9 +1,14 @@
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 
+mod config;
+mod deps;
+mod directives;
+mod event;
 mod git;
 mod package;
 mod pkgbuild;
+mod submodule;
 
 #[derive(Parser)]
 #[command(name = "syspac")]
@@ -25,6 +30,27 @@ enum Commands {
         #[arg(short, long)]
         base_ref: Option<String>,
 
+        /// Compare against the merge-base of HEAD and this branch (for pull requests)
+        #[arg(long, conflicts_with = "base_ref")]
+        merge_base: Option<String>,
+
+        /// Parents to compare a merge commit against when no base is given: first or all
+        #[arg(long, default_value = "first")]
+        parents: git::ParentMode,
+
+        /// Diff exactly the range of a GitHub push or pull_request event payload
+        /// (e.g. $GITHUB_EVENT_PATH)
+        #[arg(long, conflicts_with_all = ["base_ref", "merge_base", "worktree", "staged"])]
+        event: Option<String>,
+
+        /// Compare HEAD against the working directory (staged, unstaged and untracked changes)
+        #[arg(long, conflicts_with_all = ["base_ref", "merge_base", "staged"])]
+        worktree: bool,
+
+        /// Compare HEAD against the index (staged changes only)
+        #[arg(long, conflicts_with_all = ["base_ref", "merge_base"])]
+        staged: bool,
+
         /// Output format: space-separated list or JSON
         #[arg(short, long, default_value = "space")]
         format: String,
@@ -36,6 +62,43 @@ enum Commands {
         /// Return full paths instead of package names (e.g., "packages/niri" instead of "niri")
         #[arg(short, long)]
         paths: bool,
+
+        /// Also report submodule pin movements and the upstream commits they pull in
+        #[arg(long)]
+        submodule_log: bool,
+
+        /// Ignore [rebuild: ...], [rebuild-all] and [skip-build: ...] directives in commit messages
+        #[arg(long)]
+        no_directives: bool,
+
+        /// Also return in-repo packages that depend on changed packages (depends/makedepends)
+        #[arg(long)]
+        with_dependents: bool,
+
+        /// Categories to report, comma-separated: added, modified, removed, renamed
+        /// (JSON output becomes an object with one list per category)
+        #[arg(long, value_delimiter = ',')]
+        categories: Option<Vec<git::ChangeCategory>>,
+
+        /// Explain why each package was selected: files, commits, versions and reasons
+        #[arg(long)]
+        explain: bool,
+
+        /// Only return packages whose epoch:pkgver-pkgrel changed, ignoring other file churn
+        #[arg(long)]
+        version_bumps: bool,
+
+        /// When the base commit is not available (shallow clone, force-push):
+        /// fail, deepen (fetch from --remote), last-build (see mark-built) or all
+        #[arg(long, default_value = "fail")]
+        missing_base: git::MissingBase,
+
+        /// Remote to fetch missing history from with --missing-base deepen
+        #[arg(long, default_value = "origin")]
+        remote: String,
+
+        #[command(flatten)]
+        filter: FilterArgs,
     },
 
     /// List all packages in the repository
@@ -51,6 +114,95 @@ enum Commands {
         /// Show full paths instead of package names
         #[arg(short, long)]
         paths: bool,
+
+        /// List every package the PKGBUILDs produce (each pkgname of split packages)
+        #[arg(long, conflicts_with = "paths")]
+        pkgnames: bool,
+
+        /// List the packages at this commit, branch or tag instead of the working directory
+        #[arg(long)]
+        at: Option<String>,
+
+        /// Output format: text (one package per line) or JSON with the names,
+        /// version and build settings of every package
+        #[arg(short, long, default_value = "text")]
+        format: String,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+
+    /// Record a commit as the last built state (used by --missing-base last-build)
+    MarkBuilt {
+        /// Git repository path
+        #[arg(short, long, default_value = ".")]
+        repo_path: String,
+
+        /// Commit to record
+        #[arg(default_value = "HEAD")]
+        rev: String,
+    },
+
+    /// Show the repository configuration (syspac.toml with defaults applied)
+    Config {
+        /// Git repository path
+        #[arg(short, long, default_value = ".")]
+        repo_path: String,
+
+        /// Dotted key to print, e.g. repo.name or release.tag (lists are space-separated)
+        key: Option<String>,
+    },
+
+    /// Check the git submodules for problems; exits non-zero on errors
+    CheckSubmodules {
+        /// Git repository path
+        #[arg(short, long, default_value = ".")]
+        repo_path: String,
+
+        /// Treat warnings (dirty checkouts, URL mismatches) as errors
+        #[arg(long)]
+        strict: bool,
+    },
+
+    /// Add a package repository as a submodule and stage it
+    AddPackage {
+        /// Git repository path
+        #[arg(short, long, default_value = ".")]
+        repo_path: String,
+
+        /// URL of the package repository (any git URL, including file://)
+        url: String,
+
+        /// Where to check it out (defaults to packages/<repository name>)
+        #[arg(long)]
+        path: Option<String>,
+    },
+
+    /// Remove a package: deinitialize its submodule or delete its directory, and stage it
+    RemovePackage {
+        /// Git repository path
+        #[arg(short, long, default_value = ".")]
+        repo_path: String,
+
+        /// Package name
+        name: String,
+    },
+
+    /// Fetch submodules and fast-forward their pins to the tracked branch
+    UpdateSubmodules {
+        /// Git repository path
+        #[arg(short, long, default_value = ".")]
+        repo_path: String,
+
+        /// Packages or submodules to update (all submodule packages if none)
+        packages: Vec<String>,
+
+        /// Commit each updated pin separately instead of only staging it
+        #[arg(long)]
+        commit: bool,
+
+        #[command(flatten)]
+        filter: FilterArgs,
     },
 
     /// Get package version from PKGBUILD
@@ -58,6 +210,56 @@ enum Commands {
         /// Path to PKGBUILD or package directory
         path: String,
     },
+
+    /// Show the metadata of a PKGBUILD: names, version, relations, sources and checksums
+    Info {
+        /// Package name, package directory or path to PKGBUILD
+        package: String,
+
+        /// Git repository path (to look up packages by name)
+        #[arg(short, long, default_value = ".")]
+        repo_path: String,
+
+        /// Output format: text (one variable per line) or JSON
+        #[arg(short, long, default_value = "text")]
+        format: String,
+    },
+}
+
+/// Package selection shared by the commands that work on a set of packages
+#[derive(Args)]
+struct FilterArgs {
+    /// Only packages in this group (from [groups] in syspac.toml or PKGBUILD groups=); repeatable
+    #[arg(long = "group")]
+    groups: Vec<String>,
+
+    /// Only packages whose name matches this glob; repeatable
+    #[arg(long)]
+    include: Vec<String>,
+
+    /// Skip packages whose name matches this glob; repeatable
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// Only packages vendored as git submodules
+    #[arg(long, conflicts_with = "only_direct")]
+    only_submodules: bool,
+
+    /// Only packages stored directly in the repository
+    #[arg(long)]
+    only_direct: bool,
+}
+
+impl From<FilterArgs> for package::PackageFilter {
+    fn from(args: FilterArgs) -> Self {
+        package::PackageFilter {
+            groups: args.groups,
+            include: args.include,
+            exclude: args.exclude,
+            only_submodules: args.only_submodules,
+            only_direct: args.only_direct,
+        }
+    }
 }
 
 fn main() -> Result<()> {
@@ -67,27 +269,58 @@ fn main() -> Result<()> {
         Commands::DetectChanges {
             repo_path,
             base_ref,
+            merge_base,
+            parents,
+            event,
+            worktree,
+            staged,
             format,
             all,
             paths,
+            submodule_log,
+            no_directives,
+            with_dependents,
+            categories,
+            explain,
+            version_bumps,
+            missing_base,
+            remote,
+            filter,
         } => {
-            let packages = if all {
-                // Return all packages
-                package::find_all_packages(&repo_path)?
-            } else {
-                // Return only changed packages
-                let changed_names = git::detect_changed_packages(&repo_path, base_ref.as_deref())?;
-                let all_packages = package::find_all_packages(&repo_path)?;
-
-                // Filter packages to only those that changed
-                all_packages
-                    .into_iter()
-                    .filter(|p| changed_names.contains(&p.name))
-                    .collect()
+            let mut detect_options = git::DetectOptions {
+                base_ref,
+                head_ref: None,
+                merge_base,
+                parents,
+                local: if worktree {
+                    Some(git::LocalChanges::Worktree)
+                } else if staged {
+                    Some(git::LocalChanges::Staged)
+                } else {
+                    None
+                },
+                no_directives,
+                dependents: with_dependents,
+                all,
+                categories: categories.clone(),
+                explain,
+                version_bumps,
+                missing_base,
+                remote: Some(remote),
+                filter: filter.into(),
             };
 
+            let config = config::load_repo_config(&repo_path)?;
+
+            if let Some(path) = &event {
+                event::load_event(path)?.apply(&repo_path, &mut detect_options)?;
+            }
+
+            let report = git::detect_changed_packages(&repo_path, &config, &detect_options)?;
+
             // Extract either names or paths
-            let output: Vec<String> = packages
+            let output: Vec<String> = report
+                .packages
                 .iter()
                 .map(|p| {
                     if paths {
@@ -98,12 +331,68 @@ fn main() -> Result<()> {
                 })
                 .collect();
 
+            let submodule_updates = if submodule_log {
+                Some(git::find_submodule_updates(
+                    &repo_path,
+                    &config,
+                    &detect_options,
+                    &report,
+                )?)
+            } else {
+                None
+            };
+
             match format.as_str() {
                 "json" => {
-                    println!("{}", serde_json::to_string_pretty(&output)?);
+                    let mut value = if explain {
+                        serde_json::to_value(&report)?
+                    } else if let Some(categories) = &categories {
+                        categorized_output(&report, categories, paths)
+                    } else {
+                        serde_json::to_value(&output)?
+                    };
+
+                    if let Some(updates) = submodule_updates {
+                        value = match value {
+                            serde_json::Value::Object(mut map) => {
+                                map.insert(
+                                    "submodules".to_string(),
+                                    serde_json::to_value(updates)?,
+                                );
+                                serde_json::Value::Object(map)
+                            }
+                            packages => serde_json::json!({
+                                "packages": packages,
+                                "submodules": updates,
+                            }),
+                        };
+                    }
+
+                    println!("{}", serde_json::to_string_pretty(&value)?);
                 }
                 "space" => {
-                    println!("{}", output.join(" "));
+                    if explain {
+                        for change in &report.packages {
+                            println!("{}", format_package_change(change));
+                        }
+                        for skip in &report.skipped {
+                            match &skip.commit {
+                                Some(commit) => println!(
+                                    "{} (skipped by directive in {})",
+                                    skip.name,
+                                    git::short_sha(Some(commit))
+                                ),
+                                None => println!("{} (skipped by skip_build setting)", skip.name),
+                            }
+                        }
+                    } else {
+                        println!("{}", output.join(" "));
+                    }
+
+                    // Keep stdout parseable; the log goes to stderr for CI output
+                    for update in submodule_updates.unwrap_or_default() {
+                        eprintln!("{}", format_submodule_update(&update));
+                    }
                 }
                 _ => {
                     anyhow::bail!("Unknown format: {}", format);
@@ -115,24 +404,184 @@ fn main() -> Result<()> {
             repo_path,
             verbose,
             paths,
+            pkgnames,
+            at,
+            format,
+            filter,
         } => {
-            let packages = package::find_all_packages(&repo_path)?;
+            // A bare repository has no working directory to scan
+            let at = at.or_else(|| package::is_bare_repo(&repo_path).then(|| "HEAD".to_string()));
 
-            for pkg in packages {
-                let identifier = if paths { &pkg.path } else { &pkg.name };
+            let config = config::load_repo_config(&repo_path)?;
+            let packages = match &at {
+                Some(rev) => package::find_packages_at(&repo_path, rev, &config.packages)?,
+                None => package::find_all_packages(&repo_path, &config.packages)?,
+            };
+            package::validate_names(&packages)?;
+            let packages = package::PackageFilter::from(filter)
+                .compile(&config, &packages)?
+                .select(packages);
+
+            let json = match format.as_str() {
+                "text" => false,
+                "json" => true,
+                _ => anyhow::bail!("Unknown format: {}", format),
+            };
+            let with_versions = verbose || json;
+
+            // Versions at a ref come from the PKGBUILD blobs, not the checkout
+            let versions = match (&at, with_versions) {
+                (Some(rev), true) => git::read_versions_at(&repo_path, rev, &packages)?,
+                _ => Vec::new(),
+            };
+            let version = |i: usize, pkg: &package::Package| match (&at, with_versions) {
+                (_, false) => None,
+                (Some(_), true) => versions[i].clone(),
+                (None, true) => pkgbuild::parse_version(&pkg.pkgbuild_path).ok(),
+            };
 
-                if verbose {
-                    if let Ok(version) = pkgbuild::parse_version(&pkg.pkgbuild_path) {
-                        println!("{}: {}", identifier, version);
+            if json {
+                let entries: Vec<serde_json::Value> = packages
+                    .iter()
+                    .enumerate()
+                    .map(|(i, pkg)| {
+                        serde_json::json!({
+                            "name": pkg.name,
+                            "path": pkg.path,
+                            "pkgbase": pkg.pkgbase,
+                            "pkgnames": pkg.pkgnames,
+                            "groups": pkg.groups,
+                            "submodule": pkg.submodule.as_ref().map(|s| &s.name),
+                            "version": version(i, pkg).map(|v| v.to_string()),
+                            "settings": pkg.settings,
+                        })
+                    })
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+            } else {
+                for (i, pkg) in packages.iter().enumerate() {
+                    let identifiers = if pkgnames {
+                        pkg.produced_names()
+                    } else if paths {
+                        vec![pkg.path.as_str()]
                     } else {
-                        println!("{}: <version unknown>", identifier);
+                        vec![pkg.name.as_str()]
+                    };
+
+                    let version = version(i, pkg);
+
+                    for identifier in identifiers {
+                        if !verbose {
+                            println!("{}", identifier);
+                        } else if let Some(version) = &version {
+                            println!("{}: {}", identifier, version);
+                        } else {
+                            println!("{}: <version unknown>", identifier);
+                        }
                     }
-                } else {
-                    println!("{}", identifier);
                 }
             }
         }
 
+        Commands::MarkBuilt { repo_path, rev } => {
+            // Fail early on an invalid configuration, like every other command
+            config::load_repo_config(&repo_path)?;
+
+            let commit = git::mark_built(&repo_path, &rev)?;
+            println!("{}", commit);
+        }
+
+        Commands::Config { repo_path, key } => {
+            let config = serde_json::to_value(config::load_repo_config(&repo_path)?)?;
+
+            match key {
+                Some(key) => {
+                    let value = key
+                        .split('.')
+                        .try_fold(&config, |value, part| value.get(part))
+                        .ok_or_else(|| anyhow::anyhow!("Unknown config key: {}", key))?;
+                    println!("{}", format_config_value(value));
+                }
+                None => println!("{}", serde_json::to_string_pretty(&config)?),
+            }
+        }
+
+        Commands::CheckSubmodules { repo_path, strict } => {
+            // Fail early on an invalid configuration, like every other command
+            config::load_repo_config(&repo_path)?;
+
+            let issues = submodule::check_submodules(&repo_path)?;
+            for issue in &issues {
+                println!("{}", issue);
+            }
+
+            let errors = issues
+                .iter()
+                .filter(|issue| strict || issue.severity == submodule::Severity::Error)
+                .count();
+            if errors > 0 {
+                anyhow::bail!("{} submodule problem(s) found", errors);
+            }
+        }
+
+        Commands::AddPackage {
+            repo_path,
+            url,
+            path,
+        } => {
+            let config = config::load_repo_config(&repo_path)?;
+            let packages =
+                submodule::add_package(&repo_path, &url, path.as_deref(), &config.packages)?;
+
+            for pkg in &packages {
+                match pkgbuild::parse_version(&pkg.pkgbuild_path) {
+                    Ok(version) => println!("{} ({}): {}", pkg.name, pkg.path, version),
+                    Err(_) => println!("{} ({})", pkg.name, pkg.path),
+                }
+            }
+            eprintln!("Staged the submodule and .gitmodules; commit to finish adding it");
+        }
+
+        Commands::RemovePackage { repo_path, name } => {
+            let config = config::load_repo_config(&repo_path)?;
+            let pkgnames = submodule::remove_package(&repo_path, &name, &config.packages)?;
+
+            // The pkgnames that leave the repo database
+            for pkgname in pkgnames {
+                println!("{}", pkgname);
+            }
+            eprintln!(
+                "Staged the removal of {}; commit to finish removing it",
+                name
+            );
+        }
+
+        Commands::UpdateSubmodules {
+            repo_path,
+            packages,
+            commit,
+            filter,
+        } => {
+            let config = config::load_repo_config(&repo_path)?;
+            let (updates, failures) = submodule::update_submodules(
+                &repo_path,
+                &packages,
+                &filter.into(),
+                commit,
+                &config,
+            )?;
+
+            for update in &updates {
+                println!("{}", update);
+            }
+            for failure in &failures {
+                eprintln!("error: {:#}", failure);
+            }
+            if !failures.is_empty() {
+                anyhow::bail!("{} submodule(s) could not be updated", failures.len());
+            }
+        }
+
         Commands::PackageVersion { path } => {
             let pkgbuild_path = if path.ends_with("PKGBUILD") {
                 path
@@ -140,10 +589,171 @@ fn main() -> Result<()> {
                 format!("{}/PKGBUILD", path.trim_end_matches('/'))
             };
 
-            let version = pkgbuild::parse_version(&pkgbuild_path)?;
-            println!("{}", version);
+            let vars = pkgbuild::parse_pkgbuild(&pkgbuild_path)?;
+            for diagnostic in &vars.diagnostics {
+                eprintln!("warning: {}: {}", pkgbuild_path, diagnostic);
+            }
+            println!("{}", pkgbuild::PackageVersion::from_vars(&vars)?);
+        }
+
+        Commands::Info {
+            package,
+            repo_path,
+            format,
+        } => {
+            let pkgbuild_path = if package.ends_with("PKGBUILD") {
+                package
+            } else if std::path::Path::new(&package).is_dir() {
+                format!("{}/PKGBUILD", package.trim_end_matches('/'))
+            } else {
+                let config = config::load_repo_config(&repo_path)?;
+                package::find_all_packages(&repo_path, &config.packages)?
+                    .into_iter()
+                    .find(|p| p.name == package || p.pkgnames.contains(&package))
+                    .map(|p| p.pkgbuild_path)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown package: {}", package))?
+            };
+
+            let vars = pkgbuild::parse_pkgbuild(&pkgbuild_path)?;
+            for diagnostic in &vars.diagnostics {
+                eprintln!("warning: {}: {}", pkgbuild_path, diagnostic);
+            }
+            let info = pkgbuild::Pkgbuild::from_vars(&vars)?;
+
+            match format.as_str() {
+                "json" => println!("{}", serde_json::to_string_pretty(&info)?),
+                "text" => {
+                    if let serde_json::Value::Object(fields) = serde_json::to_value(&info)? {
+                        for (name, value) in &fields {
+                            let value = format_config_value(value);
+                            if !value.is_empty() {
+                                println!("{}: {}", name, value);
+                            }
+                        }
+                    }
+                }
+                _ => anyhow::bail!("Unknown format: {}", format),
+            }
         }
     }
 
     Ok(())
 }
+
+/// Builds a JSON object with one list per requested category
+/// Renamed packages are reported as `{"from": ..., "to": ...}` pairs
+fn categorized_output(
+    report: &git::ChangeReport,
+    categories: &[git::ChangeCategory],
+    paths: bool,
+) -> serde_json::Value {
+    let identifier = |name: &str, path: &str| {
+        if paths {
+            path.to_string()
+        } else {
+            name.to_string()
+        }
+    };
+
+    let mut object = serde_json::Map::new();
+
+    for category in git::ChangeCategory::ALL {
+        if !categories.contains(category) {
+            continue;
+        }
+
+        let entries: Vec<serde_json::Value> = report
+            .packages
+            .iter()
+            .filter(|change| change.category == *category)
+            .map(|change| {
+                let current = identifier(&change.name, &change.path);
+                match &change.renamed_from {
+                    Some(from) => serde_json::json!({
+                        "from": identifier(&from.name, &from.path),
+                        "to": current,
+                    }),
+                    None => serde_json::Value::String(current),
+                }
+            })
+            .collect();
+
+        object.insert(category.as_str().to_string(), entries.into());
+    }
+
+    serde_json::Value::Object(object)
+}
+
+/// Formats a config value for shell scripts: strings unquoted, lists
+/// space-separated, null as an empty line
+fn format_config_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::Array(items) => items
+            .iter()
+            .map(format_config_value)
+            .collect::<Vec<_>>()
+            .join(" "),
+        other => other.to_string(),
+    }
+}
+
+/// Formats a submodule pin movement as a short human-readable log
+fn format_submodule_update(update: &git::SubmoduleUpdate) -> String {
+    let mut out = format!(
+        "{}: {}..{}",
+        update.name,
+        git::short_sha(update.old_commit.as_deref()),
+        git::short_sha(update.new_commit.as_deref())
+    );
+
+    if !update.log_available {
+        out.push_str(" (upstream log unavailable)");
+    }
+
+    for commit in &update.commits {
+        out.push_str(&format!(
+            "\n  {} {}",
+            git::short_sha(Some(&commit.id)),
+            commit.summary
+        ));
+    }
+
+    out
+}
+
+/// Formats an explained package change as a human-readable block
+fn format_package_change(change: &git::PackageChange) -> String {
+    let mut out = format!("{} ({})", change.name, change.path);
+
+    if let Some(from) = &change.renamed_from {
+        out.push_str(&format!("\n  renamed from: {} ({})", from.name, from.path));
+    }
+
+    for reason in &change.reasons {
+        out.push_str(&format!("\n  reason: {}", reason));
+    }
+
+    if change.old_version.is_some() || change.new_version.is_some() {
+        out.push_str(&format!(
+            "\n  version: {} -> {}",
+            change.old_version.as_deref().unwrap_or("(none)"),
+            change.new_version.as_deref().unwrap_or("(none)")
+        ));
+    }
+
+    for file in &change.files {
+        out.push_str(&format!("\n  file: {}", file));
+    }
+
+    for commit in &change.commits {
+        out.push_str(&format!(
+            "\n  commit: {} {}",
+            git::short_sha(Some(&commit.id)),
+            commit.summary
+        ));
+    }
+
+    out
+}
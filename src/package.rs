@@ -1,8 +1,16 @@
 use anyhow::{Context, Result};
-use git2::Repository;
+use git2::{ObjectType, Oid, Repository, Tree};
+use globset::{Glob, GlobMatcher, GlobSet};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use crate::config::{
+    build_globset, merge_package_settings, parse_package_settings, Config, PackageSettings,
+    PackagesConfig, CONFIG_FILE,
+};
+use crate::pkgbuild;
+
 /// Represents a package in the repository
 #[derive(Debug, Clone)]
 pub struct Package {
@@ -11,25 +19,94 @@ pub struct Package {
     /// Relative path from repo root
     pub path: String,
     /// Full path to PKGBUILD
+    /// (repo-relative for packages discovered from a git tree)
     pub pkgbuild_path: String,
-    /// Whether this is a git submodule
-    pub is_submodule: bool,
+    /// The git submodule the package lives in, if any
+    pub submodule: Option<SubmoduleRef>,
+    /// pkgbase from the PKGBUILD (None if it could not be read)
+    pub pkgbase: Option<String>,
+    /// Every package the PKGBUILD produces (several for split packages)
+    pub pkgnames: Vec<String>,
+    /// Package groups from the PKGBUILD's `groups=`
+    pub groups: Vec<String>,
+    /// Build settings from the package's `syspac.toml` or the repository config
+    pub settings: PackageSettings,
+}
+
+/// A git submodule holding one or more packages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmoduleRef {
+    /// Submodule name from `.gitmodules`
+    pub name: String,
+    /// Path of the gitlink, relative to the repo root
+    pub path: String,
+}
+
+impl Package {
+    /// Whether the package lives in a git submodule
+    pub fn is_submodule(&self) -> bool {
+        self.submodule.is_some()
+    }
+
+    /// The packages this source package produces
+    /// Falls back to the package name when the PKGBUILD could not be read
+    pub fn produced_names(&self) -> Vec<&str> {
+        if self.pkgnames.is_empty() {
+            vec![self.name.as_str()]
+        } else {
+            self.pkgnames.iter().map(String::as_str).collect()
+        }
+    }
+
+    /// Fills in pkgbase and pkgnames from the PKGBUILD content
+    fn set_names(&mut self, content: Option<String>) {
+        if let Some(names) = content.and_then(|c| pkgbuild::parse_names_str(&c).ok()) {
+            self.pkgbase = Some(names.pkgbase);
+            self.pkgnames = names.pkgnames;
+            self.groups = names.groups;
+        }
+    }
+
+    /// Fills in the build settings from the `syspac.toml` content of the
+    /// package directory; keys set in the package's section of the repository
+    /// config take precedence
+    fn set_settings(&mut self, content: Option<String>, config: &PackagesConfig) -> Result<()> {
+        let invalid = || format!("Invalid {}/{}", self.path, CONFIG_FILE);
+        if let Some(content) = &content {
+            parse_package_settings(content).with_context(invalid)?;
+        }
+        self.settings =
+            merge_package_settings(content.as_deref(), config, &self.name).with_context(invalid)?;
+        Ok(())
+    }
 }
 
 /// Finds all packages in the repository
 /// This includes both git submodules and direct directories with PKGBUILD
-pub fn find_all_packages(repo_path: &str) -> Result<Vec<Package>> {
+/// A bare repository has no working directory, so its HEAD tree is used
+pub fn find_all_packages(repo_path: &str, config: &PackagesConfig) -> Result<Vec<Package>> {
     let repo = Repository::open(repo_path)
         .context(format!("Failed to open repository at {}", repo_path))?;
 
+    if repo.is_bare() {
+        return find_packages_at(repo_path, "HEAD", config);
+    }
+
     let mut packages = Vec::new();
     let repo_path_buf = PathBuf::from(repo_path);
+    let discovery = Discovery::new(config)?;
 
     // Find packages from submodules
-    packages.extend(find_submodule_packages(&repo, &repo_path_buf)?);
+    packages.extend(find_submodule_packages(&repo, &repo_path_buf, &discovery)?);
 
     // Find direct directory packages (non-submodules)
-    packages.extend(find_direct_packages(&repo_path_buf)?);
+    packages.extend(find_direct_packages(&repo, &repo_path_buf, &discovery)?);
+
+    for package in &mut packages {
+        package.set_names(fs::read_to_string(&package.pkgbuild_path).ok());
+        let settings_path = Path::new(&package.pkgbuild_path).with_file_name(CONFIG_FILE);
+        package.set_settings(fs::read_to_string(settings_path).ok(), config)?;
+    }
 
     // Sort by name for consistent output
     packages.sort_by(|a, b| a.name.cmp(&b.name));
@@ -37,8 +114,14 @@ pub fn find_all_packages(repo_path: &str) -> Result<Vec<Package>> {
     Ok(packages)
 }
 
-/// Finds packages that are git submodules with PKGBUILD
-fn find_submodule_packages(repo: &Repository, repo_path: &Path) -> Result<Vec<Package>> {
+/// Finds packages in git submodules
+/// A submodule with a PKGBUILD at its root is one package; otherwise its
+/// checkout is searched for package directories
+fn find_submodule_packages(
+    repo: &Repository,
+    repo_path: &Path,
+    discovery: &Discovery,
+) -> Result<Vec<Package>> {
     let mut packages = Vec::new();
 
     // Get submodules
@@ -48,24 +131,51 @@ fn find_submodule_packages(repo: &Repository, repo_path: &Path) -> Result<Vec<Pa
         let submodule_path = submodule.path();
         let full_path = repo_path.join(submodule_path);
         let pkgbuild_path = full_path.join("PKGBUILD");
+        let rel_path = submodule_path.to_string_lossy().to_string();
 
-        if pkgbuild_path.exists() {
-            let name = submodule
-                .name()
-                .unwrap_or_else(|| {
-                    submodule_path
-                        .file_name()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or("unknown")
-                })
-                .to_string();
+        let name = submodule_package_name(submodule.name(), &rel_path);
+        let submodule_ref = SubmoduleRef {
+            name: submodule.name().unwrap_or(&name).to_string(),
+            path: rel_path.clone(),
+        };
 
+        if pkgbuild_path.exists() {
             packages.push(Package {
                 name,
-                path: submodule_path.to_string_lossy().to_string(),
+                path: rel_path,
                 pkgbuild_path: pkgbuild_path.to_string_lossy().to_string(),
-                is_submodule: true,
+                submodule: Some(submodule_ref),
+                pkgbase: None,
+                pkgnames: Vec::new(),
+                groups: Vec::new(),
+                settings: PackageSettings::default(),
             });
+            continue;
+        }
+
+        // Not checked out, or a collection of packages
+        let Ok(sub_repo) = submodule.open() else {
+            continue;
+        };
+        let source = FsSource {
+            repo: &sub_repo,
+            workdir: &full_path,
+            prefix: &rel_path,
+        };
+        let mut found = Vec::new();
+        walk(
+            &source,
+            discovery,
+            &rel_path,
+            discovery.max_depth,
+            false,
+            &mut found,
+        )?;
+
+        for path in found {
+            let mut package = direct_package(&repo_path.join(&path), path);
+            package.submodule = Some(submodule_ref.clone());
+            packages.push(package);
         }
     }
 
@@ -73,95 +183,675 @@ fn find_submodule_packages(repo: &Repository, repo_path: &Path) -> Result<Vec<Pa
 }
 
 /// Finds packages in direct directories (not submodules)
-/// Searches up to 2 levels deep for PKGBUILD files
-fn find_direct_packages(repo_path: &Path) -> Result<Vec<Package>> {
-    let mut packages = Vec::new();
+/// Searches below the directories matching the configured roots
+fn find_direct_packages(
+    repo: &Repository,
+    repo_path: &Path,
+    discovery: &Discovery,
+) -> Result<Vec<Package>> {
+    let source = FsSource {
+        repo,
+        workdir: repo_path,
+        prefix: "",
+    };
+    let mut found = Vec::new();
+    walk(&source, discovery, "", 0, true, &mut found)?;
 
-    // Search for PKGBUILD files up to 2 levels deep
-    for entry in fs::read_dir(repo_path).context("Failed to read repository directory")? {
-        let entry = entry.context("Failed to read directory entry")?;
-        let path = entry.path();
+    Ok(found
+        .into_iter()
+        .map(|path| direct_package(&repo_path.join(&path), path))
+        .collect())
+}
 
-        // Skip if it's a git submodule (has .git directory/file)
-        if is_submodule_dir(&path) {
-            continue;
-        }
+/// Builds a direct (non-submodule) package found in the working directory
+fn direct_package(path: &Path, rel_path: String) -> Package {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown")
+        .to_string();
+
+    Package {
+        name,
+        path: rel_path,
+        pkgbuild_path: path.join("PKGBUILD").to_string_lossy().to_string(),
+        submodule: None,
+        pkgbase: None,
+        pkgnames: Vec::new(),
+        groups: Vec::new(),
+        settings: PackageSettings::default(),
+    }
+}
 
-        // Skip hidden directories and common non-package directories
-        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-            if name.starts_with('.')
-                || name == "target"
-                || name == "node_modules"
-                || name == "build-container"
-                || name == "repo"
-            {
+/// The package discovery settings, compiled
+struct Discovery {
+    /// Whether the repository root is a package root
+    root_is_package_root: bool,
+    /// Globs matching the other package roots
+    roots: Vec<GlobMatcher>,
+    /// Depth of the deepest directory a root can match (None with `**`)
+    max_root_depth: Option<usize>,
+    /// Levels below a root searched for packages
+    max_depth: usize,
+    exclude: GlobSet,
+}
+
+impl Discovery {
+    fn new(config: &PackagesConfig) -> Result<Self> {
+        let mut discovery = Discovery {
+            root_is_package_root: false,
+            roots: Vec::new(),
+            max_root_depth: Some(0),
+            max_depth: config.max_depth,
+            exclude: config.exclude_rules()?,
+        };
+
+        for root in &config.roots {
+            let root = root.trim_matches('/');
+            if root.is_empty() || root == "." {
+                discovery.root_is_package_root = true;
                 continue;
             }
+
+            let glob = Glob::new(root).context(format!("Invalid package root: {}", root))?;
+            discovery.roots.push(glob.compile_matcher());
+            discovery.max_root_depth = match discovery.max_root_depth {
+                _ if root.contains("**") => None,
+                Some(depth) => Some(depth.max(root.split('/').count())),
+                None => None,
+            };
+        }
+
+        Ok(discovery)
+    }
+
+    /// Checks whether a repo-relative directory is a package root
+    fn is_root(&self, rel_path: &str) -> bool {
+        if rel_path.is_empty() {
+            self.root_is_package_root
+        } else {
+            self.roots.iter().any(|root| root.is_match(rel_path))
         }
+    }
+}
+
+/// Directory listings for package discovery, from a checkout or a git tree
+///
+/// Paths are relative to the superproject root; a source for a submodule
+/// lists the directories below its path.
+trait DirSource {
+    /// Names of the subdirectories that may hold packages
+    fn subdirs(&self, rel_path: &str) -> Result<Vec<String>>;
+    /// Whether the directory contains a PKGBUILD
+    fn has_pkgbuild(&self, rel_path: &str) -> bool;
+}
+
+/// A working directory, honouring its `.gitignore` files
+struct FsSource<'a> {
+    repo: &'a Repository,
+    workdir: &'a Path,
+    prefix: &'a str,
+}
 
-        // Check if this directory has a PKGBUILD
-        if path.is_dir() {
-            let pkgbuild_path = path.join("PKGBUILD");
-            if pkgbuild_path.exists() {
-                let name = path
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("unknown")
-                    .to_string();
-
-                let rel_path = path
-                    .strip_prefix(repo_path)
-                    .unwrap_or(&path)
-                    .to_string_lossy()
-                    .to_string();
-
-                packages.push(Package {
-                    name,
-                    path: rel_path,
-                    pkgbuild_path: pkgbuild_path.to_string_lossy().to_string(),
-                    is_submodule: false,
-                });
+impl DirSource for FsSource<'_> {
+    fn subdirs(&self, rel_path: &str) -> Result<Vec<String>> {
+        let inner = strip_prefix(rel_path, self.prefix);
+        let dir = self.workdir.join(inner);
+        let mut names = Vec::new();
+
+        for entry in fs::read_dir(&dir).context(format!("Failed to read {}", dir.display()))? {
+            let entry = entry.context("Failed to read directory entry")?;
+            let path = entry.path();
+
+            // Skip git submodules (they have a .git directory/file)
+            if !path.is_dir() || is_submodule_dir(&path) {
                 continue;
             }
 
-            // Check one level deeper
-            if let Ok(entries) = fs::read_dir(&path) {
-                for sub_entry in entries {
-                    if let Ok(sub_entry) = sub_entry {
-                        let sub_path = sub_entry.path();
-
-                        if sub_path.is_dir() && !is_submodule_dir(&sub_path) {
-                            let pkgbuild_path = sub_path.join("PKGBUILD");
-                            if pkgbuild_path.exists() {
-                                let name = sub_path
-                                    .file_name()
-                                    .and_then(|n| n.to_str())
-                                    .unwrap_or("unknown")
-                                    .to_string();
-
-                                let rel_path = sub_path
-                                    .strip_prefix(repo_path)
-                                    .unwrap_or(&sub_path)
-                                    .to_string_lossy()
-                                    .to_string();
-
-                                packages.push(Package {
-                                    name,
-                                    path: rel_path,
-                                    pkgbuild_path: pkgbuild_path.to_string_lossy().to_string(),
-                                    is_submodule: false,
-                                });
-                            }
-                        }
-                    }
-                }
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let ignore_path = join_path(inner, name);
+            if self.repo.is_path_ignored(&ignore_path).unwrap_or(false) {
+                continue;
             }
+            names.push(name.to_string());
         }
+
+        names.sort();
+        Ok(names)
+    }
+
+    fn has_pkgbuild(&self, rel_path: &str) -> bool {
+        self.workdir
+            .join(strip_prefix(rel_path, self.prefix))
+            .join("PKGBUILD")
+            .exists()
     }
+}
+
+/// A git tree, e.g. a commit's tree
+struct TreeSource<'a> {
+    repo: &'a Repository,
+    tree: &'a Tree<'a>,
+    prefix: &'a str,
+}
+
+impl TreeSource<'_> {
+    fn subtree(&self, rel_path: &str) -> Option<Tree<'_>> {
+        let inner = strip_prefix(rel_path, self.prefix);
+        if inner.is_empty() {
+            return Some(self.tree.clone());
+        }
+
+        let entry = self.tree.get_path(Path::new(inner)).ok()?;
+        if entry.kind() != Some(ObjectType::Tree) {
+            return None;
+        }
+        entry.to_object(self.repo).ok()?.peel_to_tree().ok()
+    }
+}
+
+impl DirSource for TreeSource<'_> {
+    fn subdirs(&self, rel_path: &str) -> Result<Vec<String>> {
+        let tree = self
+            .subtree(rel_path)
+            .context(format!("Failed to read tree {}", rel_path))?;
+
+        // Gitlinks have kind Commit, so submodules are skipped here
+        Ok(tree
+            .iter()
+            .filter(|entry| entry.kind() == Some(ObjectType::Tree))
+            .filter_map(|entry| entry.name().map(str::to_string))
+            .collect())
+    }
+
+    fn has_pkgbuild(&self, rel_path: &str) -> bool {
+        self.subtree(rel_path)
+            .is_some_and(|tree| tree.get_name("PKGBUILD").is_some())
+    }
+}
+
+impl TreeSource<'_> {
+    /// Reads a file blob of a directory, e.g. its PKGBUILD
+    fn read_file(&self, rel_path: &str, file_name: &str) -> Option<String> {
+        let blob = self
+            .subtree(rel_path)?
+            .get_name(file_name)?
+            .to_object(self.repo)
+            .ok()?
+            .peel_to_blob()
+            .ok()?;
+        Some(String::from_utf8_lossy(blob.content()).to_string())
+    }
+}
+
+/// Collects the directories holding a PKGBUILD below `rel_path`
+///
+/// `budget` is the number of levels below `rel_path` still searched; it is
+/// reset to the max depth at every package root when `match_roots` is set.
+/// Package directories are not searched further.
+fn walk(
+    source: &dyn DirSource,
+    discovery: &Discovery,
+    rel_path: &str,
+    budget: usize,
+    match_roots: bool,
+    found: &mut Vec<String>,
+) -> Result<()> {
+    let budget = if match_roots && discovery.is_root(rel_path) {
+        discovery.max_depth
+    } else {
+        budget
+    };
+
+    // Without budget, only descend to reach roots that may lie deeper
+    if budget == 0 {
+        let depth = rel_path.split('/').filter(|c| !c.is_empty()).count();
+        let roots_below = discovery.max_root_depth.is_none_or(|max| depth < max);
+        if !match_roots || !roots_below {
+            return Ok(());
+        }
+    }
+
+    for name in source.subdirs(rel_path)? {
+        let path = join_path(rel_path, &name);
+        if is_excluded(&path, &discovery.exclude) {
+            continue;
+        }
+
+        if budget > 0 && source.has_pkgbuild(&path) {
+            if !found.contains(&path) {
+                found.push(path);
+            }
+            continue;
+        }
+
+        walk(
+            source,
+            discovery,
+            &path,
+            budget.saturating_sub(1),
+            match_roots,
+            found,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Joins a relative directory and a name with `/`
+fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Strips a directory prefix from a relative path
+fn strip_prefix<'a>(path: &'a str, prefix: &str) -> &'a str {
+    path.strip_prefix(prefix)
+        .unwrap_or(path)
+        .trim_start_matches('/')
+}
+
+/// Narrows a package selection by group, name globs and kind
+///
+/// Every given criterion must hold; several groups or include globs
+/// select the packages matching any of them.
+#[derive(Debug, Clone, Default)]
+pub struct PackageFilter {
+    /// Groups from `[groups]` in syspac.toml or the PKGBUILDs' `groups=`
+    pub groups: Vec<String>,
+    /// Globs a package name must match
+    pub include: Vec<String>,
+    /// Globs a package name must not match
+    pub exclude: Vec<String>,
+    /// Only packages in git submodules
+    pub only_submodules: bool,
+    /// Only packages in directories of the repository itself
+    pub only_direct: bool,
+}
+
+/// A [`PackageFilter`] compiled against the configured groups
+pub struct PackageSelector {
+    /// Per requested group, the configured member globs (if any)
+    groups: Vec<(String, Option<GlobSet>)>,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    only_submodules: bool,
+    only_direct: bool,
+}
+
+impl PackageFilter {
+    /// Compiles the filter; a group must be configured or used by a package
+    pub fn compile(&self, config: &Config, packages: &[Package]) -> Result<PackageSelector> {
+        let mut groups = Vec::new();
+        for group in &self.groups {
+            let members = config
+                .groups
+                .get(group)
+                .map(|members| {
+                    build_globset(members).context(format!("Invalid members of group {}", group))
+                })
+                .transpose()?;
+
+            if members.is_none() && !packages.iter().any(|p| p.groups.contains(group)) {
+                anyhow::bail!("Unknown group: {}", group);
+            }
+            groups.push((group.clone(), members));
+        }
+
+        Ok(PackageSelector {
+            groups,
+            include: (!self.include.is_empty())
+                .then(|| build_globset(&self.include))
+                .transpose()
+                .context("Invalid --include pattern")?,
+            exclude: build_globset(&self.exclude).context("Invalid --exclude pattern")?,
+            only_submodules: self.only_submodules,
+            only_direct: self.only_direct,
+        })
+    }
+}
+
+impl PackageSelector {
+    /// Checks whether the filter selects a package
+    pub fn matches(&self, package: &Package) -> bool {
+        let in_group = |(group, members): &(String, Option<GlobSet>)| {
+            members.as_ref().is_some_and(|m| m.is_match(&package.name))
+                || package.groups.contains(group)
+        };
+
+        (self.groups.is_empty() || self.groups.iter().any(in_group))
+            && self
+                .include
+                .as_ref()
+                .is_none_or(|include| include.is_match(&package.name))
+            && !self.exclude.is_match(&package.name)
+            && (!self.only_submodules || package.is_submodule())
+            && (!self.only_direct || !package.is_submodule())
+    }
+
+    /// Keeps the selected packages
+    pub fn select(&self, packages: Vec<Package>) -> Vec<Package> {
+        packages.into_iter().filter(|p| self.matches(p)).collect()
+    }
+}
+
+/// Checks the package names before they are used
+///
+/// Two packages with the same name are an error. A package whose
+/// `.gitmodules` name, directory name and pkgbase disagree gets a warning
+/// on stderr.
+pub fn validate_names(packages: &[Package]) -> Result<()> {
+    let (errors, warnings) = check_names(packages);
+
+    for warning in &warnings {
+        eprintln!("warning: {}", warning);
+    }
+    if !errors.is_empty() {
+        anyhow::bail!("{}", errors.join("\n"));
+    }
+
+    Ok(())
+}
+
+/// Collects the name errors and warnings of the packages
+fn check_names(packages: &[Package]) -> (Vec<String>, Vec<String>) {
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+
+    let mut by_name: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for package in packages {
+        by_name
+            .entry(&package.name)
+            .or_default()
+            .push(&package.path);
+    }
+    for (name, paths) in by_name.into_iter().filter(|(_, paths)| paths.len() > 1) {
+        errors.push(format!(
+            "Duplicate package name {}: {}",
+            name,
+            paths.join(", ")
+        ));
+    }
+
+    for package in packages {
+        let mut names: Vec<(&str, &str)> = Vec::new();
+        if let Some(submodule) = package
+            .submodule
+            .as_ref()
+            .filter(|s| s.path == package.path && s.name != s.path)
+        {
+            names.push((".gitmodules name", &submodule.name));
+        }
+        let dir_name = package.path.rsplit('/').next().unwrap_or(&package.path);
+        names.push(("directory", dir_name));
+        if let Some(pkgbase) = &package.pkgbase {
+            names.push(("pkgbase", pkgbase));
+        }
+
+        if names.iter().any(|(_, name)| *name != names[0].1) {
+            let names: Vec<String> = names
+                .iter()
+                .map(|(label, name)| format!("{} `{}`", label, name))
+                .collect();
+            warnings.push(format!("{}: {} disagree", package.path, names.join(", ")));
+        }
+    }
+
+    (errors, warnings)
+}
+
+/// The name of the package at the root of a submodule: its `.gitmodules`
+/// name, or its directory name when the submodule is named after its path
+/// (the default of `git submodule add`)
+fn submodule_package_name(name: Option<&str>, path: &str) -> String {
+    match name {
+        Some(name) if name != path => name.to_string(),
+        _ => path.rsplit('/').next().unwrap_or(path).to_string(),
+    }
+}
+
+/// A submodule entry from a `.gitmodules` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitmodulesEntry {
+    pub name: String,
+    pub path: String,
+    pub url: Option<String>,
+    pub branch: Option<String>,
+}
+
+/// Checks whether the repository at the path is bare (has no working directory)
+pub fn is_bare_repo(repo_path: &str) -> bool {
+    Repository::open(repo_path).is_ok_and(|repo| repo.is_bare())
+}
+
+/// Finds all packages at a revision (commit, branch or tag), without a checkout
+pub fn find_packages_at(
+    repo_path: &str,
+    rev: &str,
+    config: &PackagesConfig,
+) -> Result<Vec<Package>> {
+    let repo = Repository::open(repo_path)
+        .context(format!("Failed to open repository at {}", repo_path))?;
+
+    let tree = repo
+        .revparse_single(rev)
+        .context(format!("Failed to parse ref: {}", rev))?
+        .peel_to_tree()
+        .context(format!("Failed to peel {} to a tree", rev))?;
+
+    find_packages_in_tree(&repo, &tree, config)
+}
+
+/// Finds all packages recorded in a git tree (e.g. a commit's tree)
+///
+/// Applies the same rules as the working-directory scan, but reads
+/// directories and `.gitmodules` from the object database. Submodules are
+/// taken from `.gitmodules` and their gitlinks, without requiring a checkout;
+/// their contents are read from the submodule's object store when available.
+pub fn find_packages_in_tree(
+    repo: &Repository,
+    tree: &Tree,
+    config: &PackagesConfig,
+) -> Result<Vec<Package>> {
+    let mut packages = Vec::new();
+    let discovery = Discovery::new(config)?;
+
+    // Find packages from submodules listed in the tree's .gitmodules
+    for module in read_gitmodules(repo, tree)? {
+        let commit = match tree.get_path(Path::new(&module.path)) {
+            Ok(entry) if entry.kind() == Some(ObjectType::Commit) => entry.id(),
+            _ => continue,
+        };
+        let submodule_ref = SubmoduleRef {
+            name: module.name.clone(),
+            path: module.path.clone(),
+        };
+
+        // Look inside the submodule when its commit is available
+        let sub_repo = open_submodule_repo(repo, &module.name);
+        let sub_tree = sub_repo
+            .as_ref()
+            .and_then(|sub_repo| sub_repo.find_commit(commit).ok()?.tree().ok());
+
+        let source = match (&sub_repo, &sub_tree) {
+            (Some(sub_repo), Some(sub_tree)) => Some(TreeSource {
+                repo: sub_repo,
+                tree: sub_tree,
+                prefix: &module.path,
+            }),
+            _ => None,
+        };
+
+        let found = match &source {
+            Some(source) if !source.has_pkgbuild(&module.path) => {
+                let mut found = Vec::new();
+                walk(
+                    source,
+                    &discovery,
+                    &module.path,
+                    discovery.max_depth,
+                    false,
+                    &mut found,
+                )?;
+                found
+            }
+            _ => vec![module.path.clone()],
+        };
+
+        for path in found {
+            let mut package = direct_tree_package(&path);
+            if path == module.path {
+                package.name = submodule_package_name(Some(&module.name), &module.path);
+            }
+            package.submodule = Some(submodule_ref.clone());
+            package.set_names(source.as_ref().and_then(|s| s.read_file(&path, "PKGBUILD")));
+            package.set_settings(
+                source
+                    .as_ref()
+                    .and_then(|s| s.read_file(&path, CONFIG_FILE)),
+                config,
+            )?;
+            packages.push(package);
+        }
+    }
+
+    // Find direct directory packages below the package roots
+    let source = TreeSource {
+        repo,
+        tree,
+        prefix: "",
+    };
+    let mut found = Vec::new();
+    walk(&source, &discovery, "", 0, true, &mut found)?;
+    for path in &found {
+        let mut package = direct_tree_package(path);
+        package.set_names(source.read_file(path, "PKGBUILD"));
+        package.set_settings(source.read_file(path, CONFIG_FILE), config)?;
+        packages.push(package);
+    }
+
+    // Sort by name for consistent output
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
 
     Ok(packages)
 }
 
+/// Reads the submodule entries of the `.gitmodules` file in a git tree
+pub fn read_gitmodules(repo: &Repository, tree: &Tree) -> Result<Vec<GitmodulesEntry>> {
+    match tree.get_name(".gitmodules") {
+        Some(entry) => {
+            let blob = entry
+                .to_object(repo)?
+                .peel_to_blob()
+                .context("Failed to read .gitmodules blob")?;
+            Ok(parse_gitmodules(&String::from_utf8_lossy(blob.content())))
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Opens the object store of a submodule
+/// Prefers the checked-out submodule, falling back to `.git/modules/<name>`
+pub fn open_submodule_repo(repo: &Repository, name: &str) -> Option<Repository> {
+    if let Ok(submodule) = repo.find_submodule(name) {
+        if let Ok(sub_repo) = submodule.open() {
+            return Some(sub_repo);
+        }
+    }
+
+    // Opened as bare: the recorded worktree may be gone with the checkout
+    Repository::open_bare(repo.path().join("modules").join(name)).ok()
+}
+
+/// Finds the submodule gitlink containing a repo-relative path, if any
+/// Returns the gitlink path and its pinned commit
+pub fn find_gitlink(tree: &Tree, path: &str) -> Option<(String, Oid)> {
+    let mut prefix = String::new();
+    for component in path.split('/') {
+        prefix = join_path(&prefix, component);
+        match tree.get_path(Path::new(&prefix)).ok()? {
+            entry if entry.kind() == Some(ObjectType::Commit) => return Some((prefix, entry.id())),
+            entry if entry.kind() == Some(ObjectType::Tree) => {}
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Builds a direct (non-submodule) package found in a git tree
+/// (also describes a package known only by its path, e.g. a removed one)
+pub fn direct_tree_package(path: &str) -> Package {
+    Package {
+        name: path.rsplit('/').next().unwrap_or(path).to_string(),
+        path: path.to_string(),
+        pkgbuild_path: format!("{}/PKGBUILD", path),
+        submodule: None,
+        pkgbase: None,
+        pkgnames: Vec::new(),
+        groups: Vec::new(),
+        settings: PackageSettings::default(),
+    }
+}
+
+/// Parses the submodule entries of a `.gitmodules` file
+pub fn parse_gitmodules(content: &str) -> Vec<GitmodulesEntry> {
+    let mut entries: Vec<GitmodulesEntry> = Vec::new();
+    let mut current: Option<GitmodulesEntry> = None;
+
+    for line in content.lines() {
+        let line = line.trim();
+
+        // Skip comments and empty lines
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(section) = line.strip_prefix('[') {
+            entries.extend(current.take().filter(|e| !e.path.is_empty()));
+
+            let name = section
+                .trim_end_matches(']')
+                .trim()
+                .strip_prefix("submodule")
+                .map(|rest| rest.trim().trim_matches('"').to_string());
+
+            current = name.map(|name| GitmodulesEntry {
+                name,
+                path: String::new(),
+                url: None,
+                branch: None,
+            });
+            continue;
+        }
+
+        let (Some(entry), Some((key, value))) = (current.as_mut(), line.split_once('=')) else {
+            continue;
+        };
+
+        let value = value.trim().trim_matches('"').to_string();
+        match key.trim() {
+            "path" => entry.path = value,
+            "url" => entry.url = Some(value),
+            "branch" => entry.branch = Some(value),
+            _ => {}
+        }
+    }
+
+    entries.extend(current.filter(|e| !e.path.is_empty()));
+    entries
+}
+
+/// Checks if a directory, relative to the repo root, is never a package location
+fn is_excluded(rel_path: &str, exclude: &GlobSet) -> bool {
+    let name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+    name.starts_with('.') || exclude.is_match(rel_path) || exclude.is_match(name)
+}
+
 /// Checks if a directory is a git submodule
 fn is_submodule_dir(path: &Path) -> bool {
     // A submodule has either a .git file (pointing to parent repo) or .git directory
@@ -178,9 +868,167 @@ mod tests {
         assert!(!is_submodule_dir(Path::new("/nonexistent")));
     }
 
+    #[test]
+    fn test_is_excluded() {
+        let exclude = PackagesConfig::default().exclude_rules().unwrap();
+        assert!(is_excluded(".github", &exclude));
+        assert!(is_excluded("build-container", &exclude));
+        assert!(is_excluded("packages/target", &exclude));
+        assert!(!is_excluded("packages/niri", &exclude));
+    }
+
+    #[test]
+    fn test_discovery_roots() {
+        let config = PackagesConfig {
+            roots: vec!["packages/*".to_string(), "vendor".to_string()],
+            ..PackagesConfig::default()
+        };
+        let discovery = Discovery::new(&config).unwrap();
+        assert!(!discovery.is_root(""));
+        assert!(discovery.is_root("packages/core"));
+        assert!(discovery.is_root("vendor"));
+        assert!(!discovery.is_root("packages"));
+        assert_eq!(discovery.max_root_depth, Some(2));
+
+        let config = PackagesConfig {
+            roots: vec![".".to_string(), "**/pkgs".to_string()],
+            ..PackagesConfig::default()
+        };
+        let discovery = Discovery::new(&config).unwrap();
+        assert!(discovery.is_root(""));
+        assert_eq!(discovery.max_root_depth, None);
+    }
+
+    fn package(name: &str, path: &str, submodule: Option<&str>, pkgbase: &str) -> Package {
+        Package {
+            name: name.to_string(),
+            path: path.to_string(),
+            pkgbuild_path: format!("{}/PKGBUILD", path),
+            submodule: submodule.map(|name| SubmoduleRef {
+                name: name.to_string(),
+                path: path.to_string(),
+            }),
+            pkgbase: Some(pkgbase.to_string()),
+            pkgnames: vec![pkgbase.to_string()],
+            groups: Vec::new(),
+            settings: PackageSettings::default(),
+        }
+    }
+
+    #[test]
+    fn test_package_filter() {
+        let mut niri = package("niri", "packages/niri", Some("niri"), "niri");
+        niri.groups = vec!["wayland".to_string()];
+        let packages = vec![
+            niri,
+            package("valent", "packages/valent", None, "valent"),
+            package("ly", "packages/ly", None, "ly"),
+        ];
+        let config =
+            crate::config::parse_config("[groups]\ndesktop = [\"val*\", \"ly\"]\n").unwrap();
+        let names = |filter: PackageFilter| -> Vec<String> {
+            filter
+                .compile(&config, &packages)
+                .unwrap()
+                .select(packages.clone())
+                .into_iter()
+                .map(|p| p.name)
+                .collect()
+        };
+
+        let groups = |groups: &[&str]| PackageFilter {
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            ..PackageFilter::default()
+        };
+        assert_eq!(names(groups(&["desktop"])), vec!["valent", "ly"]);
+        assert_eq!(
+            names(groups(&["wayland", "desktop"])),
+            vec!["niri", "valent", "ly"]
+        );
+        assert_eq!(
+            names(PackageFilter {
+                exclude: vec!["l*".to_string()],
+                ..groups(&["desktop"])
+            }),
+            vec!["valent"]
+        );
+        assert_eq!(
+            names(PackageFilter {
+                include: vec!["n*".to_string(), "ly".to_string()],
+                only_direct: true,
+                ..PackageFilter::default()
+            }),
+            vec!["ly"]
+        );
+        assert_eq!(
+            names(PackageFilter {
+                only_submodules: true,
+                ..PackageFilter::default()
+            }),
+            vec!["niri"]
+        );
+        assert!(groups(&["games"]).compile(&config, &packages).is_err());
+    }
+
+    #[test]
+    fn test_check_names() {
+        let packages = vec![
+            package("paru", "packages/paru", Some("paru"), "paru-bin"),
+            package("niri", "packages/wm/niri", None, "niri"),
+            package("niri", "packages/extra/niri", None, "niri"),
+        ];
+
+        let (errors, warnings) = check_names(&packages);
+        assert_eq!(
+            errors,
+            vec!["Duplicate package name niri: packages/wm/niri, packages/extra/niri"]
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                "packages/paru: .gitmodules name `paru`, directory `paru`, \
+                 pkgbase `paru-bin` disagree"
+            ]
+        );
+
+        assert!(validate_names(&packages[..2]).is_ok());
+        assert!(validate_names(&packages).is_err());
+    }
+
+    #[test]
+    fn test_parse_gitmodules() {
+        let content = r#"
+[submodule "valent"]
+	path = packages/valent
+	url = git@github.com:ylabidi/aur-valent.git
+[submodule "niri"]
+	path = packages/niri
+	url = https://github.com/hazayan/pkg-niri.git
+	branch = main
+"#;
+
+        let entries = parse_gitmodules(content);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "valent");
+        assert_eq!(entries[0].path, "packages/valent");
+        assert_eq!(
+            entries[0].url.as_deref(),
+            Some("git@github.com:ylabidi/aur-valent.git")
+        );
+        assert_eq!(entries[0].branch, None);
+        assert_eq!(entries[1].name, "niri");
+        assert_eq!(entries[1].branch.as_deref(), Some("main"));
+    }
+
+    #[test]
+    fn test_find_packages_at_invalid_repo() {
+        let config = PackagesConfig::default();
+        assert!(find_packages_at("/nonexistent/path", "HEAD", &config).is_err());
+    }
+
     #[test]
     fn test_find_all_packages_invalid_repo() {
-        let result = find_all_packages("/nonexistent/path");
+        let result = find_all_packages("/nonexistent/path", &PackagesConfig::default());
         assert!(result.is_err());
     }
 }
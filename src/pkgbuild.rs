@@ -1,144 +1,1493 @@
 use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::Path;
-use std::process::Command;
 
 /// Represents a parsed PKGBUILD version
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PackageVersion {
+    /// None when unset or `0`, which pacman treats the same
+    pub epoch: Option<String>,
     pub pkgver: String,
     pub pkgrel: String,
 }
 
 impl std::fmt::Display for PackageVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(epoch) = &self.epoch {
+            write!(f, "{}:", epoch)?;
+        }
         write!(f, "{}-{}", self.pkgver, self.pkgrel)
     }
 }
 
+impl PackageVersion {
+    /// Reads the version variables of a parsed PKGBUILD
+    pub fn from_vars(vars: &PkgbuildVars) -> Result<Self> {
+        let pkgver = vars
+            .scalar("pkgver")?
+            .ok_or_else(|| anyhow::anyhow!("pkgver not found in PKGBUILD"))?;
+        let pkgrel = vars
+            .scalar("pkgrel")?
+            .ok_or_else(|| anyhow::anyhow!("pkgrel not found in PKGBUILD"))?;
+
+        if pkgver.is_empty() {
+            anyhow::bail!("pkgver is empty in PKGBUILD");
+        }
+
+        if pkgrel.is_empty() {
+            anyhow::bail!("pkgrel is empty in PKGBUILD");
+        }
+
+        Ok(PackageVersion {
+            epoch: normalize_epoch(vars.scalar("epoch")?.unwrap_or_default()),
+            pkgver,
+            pkgrel,
+        })
+    }
+}
+
+/// The names a PKGBUILD builds: its pkgbase and every pkgname of a split package
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageNames {
+    /// `pkgbase`, defaulting to the first pkgname like makepkg does
+    pub pkgbase: String,
+    /// Every package the PKGBUILD produces
+    pub pkgnames: Vec<String>,
+    /// The package groups from `groups=`
+    pub groups: Vec<String>,
+}
+
+/// Checksum arrays makepkg supports, one per algorithm
+const CHECKSUM_ARRAYS: &[&str] = &[
+    "cksums",
+    "md5sums",
+    "sha1sums",
+    "sha224sums",
+    "sha256sums",
+    "sha384sums",
+    "sha512sums",
+    "b2sums",
+];
+
+/// Arrays that can have architecture-specific variants, e.g. `source_x86_64`
+const ARCH_ARRAYS: &[&str] = &[
+    "source",
+    "depends",
+    "makedepends",
+    "checkdepends",
+    "optdepends",
+    "provides",
+    "conflicts",
+    "replaces",
+];
+
+/// The metadata of a PKGBUILD, named like its variables
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Pkgbuild {
+    pub pkgbase: String,
+    pub pkgname: Vec<String>,
+    pub epoch: Option<String>,
+    pub pkgver: String,
+    pub pkgrel: String,
+    pub pkgdesc: Option<String>,
+    pub url: Option<String>,
+    pub arch: Vec<String>,
+    pub license: Vec<String>,
+    pub groups: Vec<String>,
+    pub depends: Vec<String>,
+    pub makedepends: Vec<String>,
+    pub checkdepends: Vec<String>,
+    pub optdepends: Vec<String>,
+    pub provides: Vec<String>,
+    pub conflicts: Vec<String>,
+    pub replaces: Vec<String>,
+    pub backup: Vec<String>,
+    pub options: Vec<String>,
+    pub install: Option<String>,
+    pub changelog: Option<String>,
+    pub source: Vec<String>,
+    /// The checksum arrays that are set, e.g. `sha256sums` or `b2sums`
+    #[serde(flatten)]
+    pub checksums: BTreeMap<String, Vec<String>>,
+    /// Variants of the source, checksum and relation arrays for the listed
+    /// architectures, e.g. `source_x86_64` or `sha256sums_aarch64`
+    #[serde(flatten)]
+    pub arch_arrays: BTreeMap<String, Vec<String>>,
+}
+
+impl Pkgbuild {
+    /// Reads the metadata from the evaluated variables of a PKGBUILD
+    /// Fails if a variable could not be evaluated statically
+    pub fn from_vars(vars: &PkgbuildVars) -> Result<Self> {
+        let version = PackageVersion::from_vars(vars)?;
+        let names = names_from_vars(vars)?;
+        let scalar = |name: &str| -> Result<Option<String>> {
+            Ok(vars.scalar(name)?.filter(|value| !value.is_empty()))
+        };
+        let array = |name: &str| vars.array(name);
+
+        let arch = array("arch")?;
+        let mut checksums = BTreeMap::new();
+        for name in CHECKSUM_ARRAYS {
+            if vars.get(name)?.is_some() {
+                checksums.insert(name.to_string(), array(name)?);
+            }
+        }
+
+        let mut arch_arrays = BTreeMap::new();
+        for arch in arch.iter().filter(|arch| *arch != "any") {
+            for name in ARCH_ARRAYS.iter().chain(CHECKSUM_ARRAYS) {
+                let name = format!("{}_{}", name, arch);
+                if vars.get(&name)?.is_some() {
+                    arch_arrays.insert(name.clone(), array(&name)?);
+                }
+            }
+        }
+
+        Ok(Pkgbuild {
+            pkgbase: names.pkgbase,
+            pkgname: names.pkgnames,
+            epoch: version.epoch,
+            pkgver: version.pkgver,
+            pkgrel: version.pkgrel,
+            pkgdesc: scalar("pkgdesc")?,
+            url: scalar("url")?,
+            arch,
+            license: array("license")?,
+            groups: array("groups")?,
+            depends: array("depends")?,
+            makedepends: array("makedepends")?,
+            checkdepends: array("checkdepends")?,
+            optdepends: array("optdepends")?,
+            provides: array("provides")?,
+            conflicts: array("conflicts")?,
+            replaces: array("replaces")?,
+            backup: array("backup")?,
+            options: array("options")?,
+            install: scalar("install")?,
+            changelog: scalar("changelog")?,
+            source: array("source")?,
+            checksums,
+            arch_arrays,
+        })
+    }
+}
+
 /// Parses version information from a PKGBUILD file
 ///
-/// This uses bash to source the PKGBUILD and extract variables,
-/// which is the most reliable way to handle complex PKGBUILDs
+/// The PKGBUILD is evaluated statically (see [`parse_pkgbuild_str`]), so
+/// nothing in it is executed
 pub fn parse_version(pkgbuild_path: &str) -> Result<PackageVersion> {
-    let path = Path::new(pkgbuild_path);
-
-    if !path.exists() {
+    if !Path::new(pkgbuild_path).exists() {
         anyhow::bail!("PKGBUILD not found at: {}", pkgbuild_path);
     }
 
-    // Use bash to source the PKGBUILD and print the variables
-    let output = Command::new("bash")
-        .arg("-c")
-        .arg(format!(
-            "source '{}' 2>/dev/null && echo \"$pkgver\" && echo \"$pkgrel\"",
-            pkgbuild_path
-        ))
-        .output()
-        .context("Failed to execute bash to parse PKGBUILD")?;
+    PackageVersion::from_vars(&parse_pkgbuild(pkgbuild_path)?)
+}
+
+/// Parses `epoch`, `pkgver` and `pkgrel` from PKGBUILD content, e.g. a blob
+/// read from git history
+pub fn parse_version_str(content: &str) -> Result<PackageVersion> {
+    PackageVersion::from_vars(&parse_pkgbuild_str(content))
+}
 
-    if !output.status.success() {
-        anyhow::bail!("Failed to source PKGBUILD at: {}", pkgbuild_path);
+/// Parses `pkgbase`, `pkgname` and `groups` from PKGBUILD content
+///
+/// Only top-level assignments are read (function bodies are skipped), so
+/// `pkgname` overrides in `package_*()` functions do not count.
+pub fn parse_names_str(content: &str) -> Result<PackageNames> {
+    names_from_vars(&parse_pkgbuild_str(content))
+}
+
+/// Reads pkgbase and pkgname of a parsed PKGBUILD
+fn names_from_vars(vars: &PkgbuildVars) -> Result<PackageNames> {
+    let pkgnames: Vec<String> = vars
+        .array("pkgname")?
+        .into_iter()
+        .filter(|name| !name.is_empty())
+        .collect();
+    let pkgbase = vars
+        .scalar("pkgbase")?
+        .filter(|base| !base.is_empty())
+        .or_else(|| pkgnames.first().cloned())
+        .ok_or_else(|| anyhow::anyhow!("pkgname not found in PKGBUILD"))?;
+
+    // Groups only select packages; unknown groups must not hide the names
+    let groups = vars.array("groups").unwrap_or_default();
+
+    Ok(PackageNames {
+        pkgbase,
+        pkgnames,
+        groups,
+    })
+}
+
+/// Treats an empty or zero epoch as no epoch
+fn normalize_epoch(epoch: String) -> Option<String> {
+    match epoch.as_str() {
+        "" | "0" => None,
+        _ => Some(epoch),
     }
+}
 
-    let stdout =
-        String::from_utf8(output.stdout).context("Failed to parse bash output as UTF-8")?;
+/// Extracts the elements of the given array variables from PKGBUILD content
+///
+/// Returns one list per requested array, in order. Unset arrays yield an
+/// empty list; an array that cannot be evaluated statically is an error.
+pub fn parse_arrays_str(content: &str, names: &[&str]) -> Result<Vec<Vec<String>>> {
+    let vars = parse_pkgbuild_str(content);
+    names
+        .iter()
+        .map(|name| {
+            Ok(vars
+                .array(name)?
+                .into_iter()
+                .filter(|element| !element.trim().is_empty())
+                .collect())
+        })
+        .collect()
+}
 
-    let mut lines = stdout.lines();
+/// A variable assigned at the top level of a PKGBUILD
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Scalar(String),
+    Array(Vec<String>),
+}
 
-    let pkgver = lines
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("pkgver not found in PKGBUILD"))?
-        .trim()
-        .to_string();
+impl Value {
+    /// The value in a scalar context: an array's first element, like in bash
+    pub fn first(&self) -> &str {
+        match self {
+            Value::Scalar(value) => value,
+            Value::Array(items) => items.first().map_or("", String::as_str),
+        }
+    }
 
-    let pkgrel = lines
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("pkgrel not found in PKGBUILD"))?
-        .trim()
-        .to_string();
+    /// The elements; a scalar is a one-element array, like in bash
+    pub fn elements(&self) -> Vec<String> {
+        match self {
+            Value::Scalar(value) => vec![value.clone()],
+            Value::Array(items) => items.clone(),
+        }
+    }
+}
+
+/// A construct the static PKGBUILD parser could not evaluate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Line number, starting at 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
 
-    if pkgver.is_empty() {
-        anyhow::bail!("pkgver is empty in PKGBUILD");
+/// The top-level variables of a PKGBUILD, evaluated without executing it
+#[derive(Debug, Clone, Default)]
+pub struct PkgbuildVars {
+    values: HashMap<String, Value>,
+    /// Variables whose value depends on something that was not evaluated
+    unresolved: HashMap<String, Diagnostic>,
+    /// Everything that could not be evaluated, in file order
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl PkgbuildVars {
+    /// Gets a variable; an error if its value could not be determined
+    pub fn get(&self, name: &str) -> Result<Option<&Value>> {
+        if let Some(diagnostic) = self.unresolved.get(name) {
+            anyhow::bail!("{} cannot be evaluated statically ({})", name, diagnostic);
+        }
+        Ok(self.values.get(name))
     }
 
-    if pkgrel.is_empty() {
-        anyhow::bail!("pkgrel is empty in PKGBUILD");
+    /// Gets a variable in a scalar context
+    pub fn scalar(&self, name: &str) -> Result<Option<String>> {
+        Ok(self.get(name)?.map(|value| value.first().to_string()))
     }
 
-    Ok(PackageVersion { pkgver, pkgrel })
+    /// Gets a variable in an array context (empty if unset)
+    pub fn array(&self, name: &str) -> Result<Vec<String>> {
+        Ok(self.get(name)?.map(Value::elements).unwrap_or_default())
+    }
 }
 
-/// Simple regex-based parser as a fallback (less reliable but doesn't require bash)
-/// Only handles simple variable assignments
-pub fn parse_version_simple(pkgbuild_path: &str) -> Result<PackageVersion> {
+/// Parses a PKGBUILD file without executing it, see [`parse_pkgbuild_str`]
+pub fn parse_pkgbuild(pkgbuild_path: &str) -> Result<PkgbuildVars> {
     let content = fs::read_to_string(pkgbuild_path)
         .context(format!("Failed to read PKGBUILD at {}", pkgbuild_path))?;
 
-    let mut pkgver = None;
-    let mut pkgrel = None;
+    Ok(parse_pkgbuild_str(&content))
+}
+
+/// Evaluates the top-level variables of PKGBUILD content without running it
+///
+/// Handles the bash subset PKGBUILDs use: quoting, (multi-line) arrays,
+/// `+=`, comments, brace expansion in arrays and `$var`/`${var}` expansion,
+/// including the `${var%suffix}`, `${var//from/to}` and `${var:-default}`
+/// forms. Function bodies are skipped. Commands, conditionals and command
+/// substitutions are never run: they are reported as diagnostics, and the
+/// variables depending on them are left unresolved.
+pub fn parse_pkgbuild_str(content: &str) -> PkgbuildVars {
+    Parser {
+        chars: content.chars().collect(),
+        pos: 0,
+        line: 1,
+        vars: PkgbuildVars::default(),
+    }
+    .parse()
+}
 
-    for line in content.lines() {
-        let line = line.trim();
+/// Compound commands: the keywords opening them and those closing them
+const BLOCK_OPENERS: &[&str] = &["if", "case", "for", "while", "until", "select"];
+const BLOCK_CLOSERS: &[&str] = &["fi", "esac", "done"];
 
-        // Skip comments and empty lines
-        if line.starts_with('#') || line.is_empty() {
-            continue;
+/// How far [`Parser::skip_compound`] skips
+#[derive(Clone, Copy)]
+enum Until {
+    /// The end of the command: a newline or `;`
+    Statement,
+    /// The `}` closing a function body or a `{ ...; }` group
+    Brace,
+    /// The keyword closing an `if`, `case` or loop
+    Block,
+}
+
+/// Evaluates PKGBUILD content statement by statement
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    vars: PkgbuildVars,
+}
+
+impl Parser {
+    fn parse(mut self) -> PkgbuildVars {
+        while let Some(c) = self.peek() {
+            match c {
+                '\\' if self.peek_at(1) == Some('\n') => {
+                    self.bump();
+                    self.bump();
+                }
+                c if c.is_whitespace() || c == ';' => {
+                    self.bump();
+                }
+                '#' => self.skip_comment(),
+                _ => self.statement(),
+            }
         }
+        self.vars
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
 
-        // Parse simple assignments
-        if line.starts_with("pkgver=") {
-            pkgver = Some(extract_value(line, "pkgver="));
-        } else if line.starts_with("pkgrel=") {
-            pkgrel = Some(extract_value(line, "pkgrel="));
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
         }
+        Some(c)
     }
 
-    let pkgver = pkgver.ok_or_else(|| anyhow::anyhow!("pkgver not found in PKGBUILD"))?;
-    let pkgrel = pkgrel.ok_or_else(|| anyhow::anyhow!("pkgrel not found in PKGBUILD"))?;
+    fn skip_comment(&mut self) {
+        while !matches!(self.peek(), Some('\n') | None) {
+            self.bump();
+        }
+    }
+
+    fn skip_blanks(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.bump();
+        }
+    }
+
+    /// Skips the word at the current position and returns it
+    fn skip_word(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| !is_word_break(c)) {
+            let end = unit_end(&self.chars, self.pos);
+            while self.pos < end {
+                self.bump();
+            }
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn diagnose(&mut self, line: usize, message: String) -> Diagnostic {
+        let diagnostic = Diagnostic { line, message };
+        self.vars.diagnostics.push(diagnostic.clone());
+        diagnostic
+    }
+
+    /// Records that a variable's value cannot be determined
+    fn unresolve(&mut self, name: &str, line: usize, message: String) {
+        let diagnostic = self.diagnose(line, message);
+        self.vars.values.remove(name);
+        self.vars.unresolved.insert(name.to_string(), diagnostic);
+    }
+
+    fn statement(&mut self) {
+        let line = self.line;
+
+        if let Some(name) = identifier_at(&self.chars, self.pos) {
+            let after = self.pos + name.chars().count();
+            match (self.chars.get(after), self.chars.get(after + 1)) {
+                (Some('='), _) => {
+                    self.pos = after + 1;
+                    return self.assignment(&name, false, line);
+                }
+                (Some('+'), Some('=')) => {
+                    self.pos = after + 2;
+                    return self.assignment(&name, true, line);
+                }
+                (Some('['), _) => {
+                    self.unresolve(
+                        &name,
+                        line,
+                        format!("{}: element assignments are not evaluated", name),
+                    );
+                    return self.skip_compound(Until::Statement, None);
+                }
+                _ => {}
+            }
+
+            self.pos = after;
+            if name == "function" {
+                self.skip_blanks();
+                let name = self.skip_word();
+                return self.function(name, line);
+            }
+            if self.skip_parens_pair() {
+                return self.function(name, line);
+            }
+            if BLOCK_OPENERS.contains(&name.as_str()) {
+                self.diagnose(line, format!("`{}` block is not evaluated", name));
+                let context = format!("inside `{}`, which is not evaluated", name);
+                return self.skip_compound(Until::Block, Some(&context));
+            }
+            self.pos -= name.chars().count();
+        }
+
+        match self.peek() {
+            Some('(') => {
+                self.diagnose(line, "subshell is not executed".to_string());
+                let end = unit_end_group(&self.chars, self.pos, '(', ')');
+                while self.pos < end {
+                    self.bump();
+                }
+            }
+            _ => {
+                let word = self.skip_word();
+                if word == "{" {
+                    self.diagnose(line, "command group is not executed".to_string());
+                    self.skip_compound(Until::Brace, Some("in a command group"));
+                } else {
+                    let word = if word.is_empty() {
+                        self.bump().map(String::from).unwrap_or_default()
+                    } else {
+                        word
+                    };
+                    self.diagnose(line, format!("command `{}` is not executed", word));
+                    self.skip_compound(Until::Statement, Some("in a command that is not executed"));
+                }
+            }
+        }
+    }
+
+    /// Skips `()` after a function name, if present
+    fn skip_parens_pair(&mut self) -> bool {
+        let (pos, line) = (self.pos, self.line);
+        self.skip_blanks();
+        if self.peek() == Some('(') {
+            self.bump();
+            self.skip_blanks();
+            if self.peek() == Some(')') {
+                self.bump();
+                return true;
+            }
+        }
+        (self.pos, self.line) = (pos, line);
+        false
+    }
+
+    /// Skips a function definition, positioned after its name
+    fn function(&mut self, name: String, line: usize) {
+        self.skip_parens_pair();
+        while let Some(c) = self.peek() {
+            match c {
+                '#' => self.skip_comment(),
+                c if c.is_whitespace() => {
+                    self.bump();
+                }
+                _ => break,
+            }
+        }
+
+        match self.peek() {
+            Some('{') => {
+                self.bump();
+                self.skip_compound(Until::Brace, None);
+            }
+            Some('(') => {
+                let end = unit_end_group(&self.chars, self.pos, '(', ')');
+                while self.pos < end {
+                    self.bump();
+                }
+            }
+            _ => {
+                self.diagnose(line, format!("function {} has no body", name));
+            }
+        }
+    }
+
+    /// Skips code without evaluating it
+    ///
+    /// With a context, assignments found in it mark their variables as
+    /// unresolved, since they may or may not take effect.
+    fn skip_compound(&mut self, until: Until, context: Option<&str>) {
+        let mut depth = 1usize;
+        let mut parens = 0usize;
+        let mut continued = false;
+        let mut heredocs: Vec<(String, bool)> = Vec::new();
+
+        while let Some(c) = self.peek() {
+            match c {
+                '\n' => {
+                    self.bump();
+                    self.skip_heredocs(&mut heredocs);
+                    if matches!(until, Until::Statement) && parens == 0 && !continued {
+                        return;
+                    }
+                }
+                '\\' if self.peek_at(1) == Some('\n') => {
+                    self.bump();
+                    self.bump();
+                }
+                c if c.is_whitespace() => {
+                    self.bump();
+                }
+                '#' => self.skip_comment(),
+                ';' if matches!(until, Until::Statement) && parens == 0 => {
+                    self.bump();
+                    return;
+                }
+                '<' if self.peek_at(1) == Some('<') && self.peek_at(2) != Some('<') => {
+                    self.bump();
+                    self.bump();
+                    heredocs.push(self.heredoc_delimiter());
+                }
+                '(' => {
+                    parens += 1;
+                    continued = false;
+                    self.bump();
+                }
+                ')' => {
+                    parens = parens.saturating_sub(1);
+                    self.bump();
+                }
+                '&' | '|' => {
+                    continued = true;
+                    self.bump();
+                }
+                ';' | '<' | '>' => {
+                    continued = false;
+                    self.bump();
+                }
+                _ => {
+                    continued = false;
+                    let line = self.line;
+                    if let Some(context) = context {
+                        if let Some(name) = assignment_at(&self.chars, self.pos) {
+                            self.unresolve(
+                                &name,
+                                line,
+                                format!("{} is assigned {}", name, context),
+                            );
+                        }
+                    }
+
+                    let word = self.skip_word();
+                    match until {
+                        Until::Brace if word == "{" => depth += 1,
+                        Until::Brace if word == "}" => depth -= 1,
+                        Until::Block if BLOCK_OPENERS.contains(&word.as_str()) => depth += 1,
+                        Until::Block if BLOCK_CLOSERS.contains(&word.as_str()) => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads the delimiter of a heredoc, positioned after `<<`
+    /// Returns the delimiter and whether leading tabs are stripped (`<<-`)
+    fn heredoc_delimiter(&mut self) -> (String, bool) {
+        let strip_tabs = self.peek() == Some('-');
+        if strip_tabs {
+            self.bump();
+        }
+        self.skip_blanks();
+        let word = self.skip_word();
+        (word.replace(['\'', '"', '\\'], ""), strip_tabs)
+    }
+
+    /// Skips the bodies of the heredocs started on the previous line
+    fn skip_heredocs(&mut self, heredocs: &mut Vec<(String, bool)>) {
+        for (delimiter, strip_tabs) in heredocs.drain(..) {
+            while self.peek().is_some() {
+                let mut line = String::new();
+                while let Some(c) = self.bump() {
+                    if c == '\n' {
+                        break;
+                    }
+                    line.push(c);
+                }
+                let line = if strip_tabs {
+                    line.trim_start_matches('\t')
+                } else {
+                    &line
+                };
+                if line == delimiter {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Evaluates an assignment, positioned after its `=` or `+=`
+    fn assignment(&mut self, name: &str, append: bool, line: usize) {
+        let value = if self.peek() == Some('(') {
+            self.bump();
+            self.array_words().map(Value::Array)
+        } else {
+            let word: Vec<char> = self.skip_word().chars().collect();
+            self.expand_word(&word, line, false)
+                .map(|fields| Value::Scalar(fields.join(" ")))
+        };
+
+        match value {
+            Ok(value) => self.assign(name, value, append),
+            Err(message) => self.unresolve(name, line, format!("{}: {}", name, message)),
+        }
+    }
+
+    /// Evaluates the words of an array, positioned after its `(`
+    fn array_words(&mut self) -> Result<Vec<String>, String> {
+        let mut words = Vec::new();
+        let mut error = None;
+
+        loop {
+            match self.peek() {
+                None => return Err("unterminated array".to_string()),
+                Some(')') => {
+                    self.bump();
+                    break;
+                }
+                Some('\\') if self.peek_at(1) == Some('\n') => {
+                    self.bump();
+                    self.bump();
+                }
+                Some('#') => self.skip_comment(),
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                }
+                Some(c) if is_word_break(c) => {
+                    self.bump();
+                    error.get_or_insert(format!("unexpected `{}` in array", c));
+                }
+                Some(_) => {
+                    let line = self.line;
+                    let word = self.skip_word();
+                    let expanded = match brace_expand(&word) {
+                        Ok(expanded) => expanded,
+                        Err(message) => {
+                            error.get_or_insert(message);
+                            continue;
+                        }
+                    };
+                    for word in expanded {
+                        let word: Vec<char> = word.chars().collect();
+                        match self.expand_word(&word, line, true) {
+                            Ok(fields) => words.extend(fields),
+                            Err(message) => {
+                                error.get_or_insert(message);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        error.map_or(Ok(words), Err)
+    }
+
+    /// Stores an evaluated assignment, with bash's `+=` and array semantics
+    fn assign(&mut self, name: &str, value: Value, append: bool) {
+        // Appending to an unknown value keeps it unknown
+        if append && self.vars.unresolved.contains_key(name) {
+            return;
+        }
+
+        let value = match (self.vars.values.remove(name), value, append) {
+            (Some(Value::Array(mut items)), Value::Array(more), true) => {
+                items.extend(more);
+                Value::Array(items)
+            }
+            (Some(Value::Scalar(first)), Value::Array(more), true) => {
+                Value::Array(std::iter::once(first).chain(more).collect())
+            }
+            (Some(Value::Scalar(mut value)), Value::Scalar(more), true) => {
+                value.push_str(&more);
+                Value::Scalar(value)
+            }
+            // A scalar assigned to an array sets its first element
+            (Some(Value::Array(mut items)), Value::Scalar(more), append) => {
+                if items.is_empty() {
+                    items.push(String::new());
+                }
+                if append {
+                    items[0].push_str(&more);
+                } else {
+                    items[0] = more;
+                }
+                Value::Array(items)
+            }
+            (_, value, _) => value,
+        };
+
+        self.vars.unresolved.remove(name);
+        self.vars.values.insert(name.to_string(), value);
+    }
+
+    /// Expands a word: removes quotes and substitutes variables
+    ///
+    /// In an array (`split`), unquoted expansions are split on whitespace and
+    /// `${array[@]}` yields one field per element; an unquoted word that
+    /// expands to nothing yields no field at all.
+    fn expand_word(
+        &mut self,
+        word: &[char],
+        line: usize,
+        split: bool,
+    ) -> Result<Vec<String>, String> {
+        let mut fields = vec![String::new()];
+        let mut quoted = false;
+        let mut i = 0;
+
+        while i < word.len() {
+            match word[i] {
+                '\'' => {
+                    let end = unit_end(word, i);
+                    let inner = &word[i + 1..end.saturating_sub(1).max(i + 1)];
+                    push_str(&mut fields, &inner.iter().collect::<String>());
+                    quoted = true;
+                    i = end;
+                }
+                '"' => {
+                    quoted = true;
+                    i += 1;
+                    while i < word.len() && word[i] != '"' {
+                        match word[i] {
+                            '\\' if matches!(word.get(i + 1), Some('$' | '`' | '"' | '\\')) => {
+                                push_str(&mut fields, &word[i + 1].to_string());
+                                i += 2;
+                            }
+                            '\\' if word.get(i + 1) == Some(&'\n') => i += 2,
+                            '$' => {
+                                let (values, end) = self.expansion(word, i, line)?;
+                                push_fields(&mut fields, values);
+                                i = end;
+                            }
+                            '`' => return Err(COMMAND_SUBSTITUTION.to_string()),
+                            c => {
+                                push_str(&mut fields, &c.to_string());
+                                i += 1;
+                            }
+                        }
+                    }
+                    i += 1;
+                }
+                '\\' => {
+                    if let Some(&c) = word.get(i + 1).filter(|&&c| c != '\n') {
+                        push_str(&mut fields, &c.to_string());
+                    }
+                    quoted = true;
+                    i += 2;
+                }
+                '$' if word.get(i + 1) == Some(&'\'') => {
+                    let end = unit_end(word, i);
+                    push_str(
+                        &mut fields,
+                        &ansi_c(&word[i + 2..end.saturating_sub(1).max(i + 2)]),
+                    );
+                    quoted = true;
+                    i = end;
+                }
+                '$' => {
+                    let (values, end) = self.expansion(word, i, line)?;
+                    if split {
+                        let words = values
+                            .iter()
+                            .flat_map(|value| value.split_whitespace())
+                            .map(str::to_string)
+                            .collect();
+                        push_fields(&mut fields, words);
+                    } else {
+                        push_str(&mut fields, &values.join(" "));
+                    }
+                    i = end;
+                }
+                '`' => return Err(COMMAND_SUBSTITUTION.to_string()),
+                c => {
+                    push_str(&mut fields, &c.to_string());
+                    i += 1;
+                }
+            }
+        }
+
+        if !quoted && fields.len() == 1 && fields[0].is_empty() && split {
+            return Ok(Vec::new());
+        }
+        if !split {
+            return Ok(vec![fields.join(" ")]);
+        }
+        Ok(fields)
+    }
+
+    /// Evaluates the `$` expansion starting at `i`
+    /// Returns its values (several for `${array[@]}`) and the index after it
+    fn expansion(
+        &mut self,
+        word: &[char],
+        i: usize,
+        line: usize,
+    ) -> Result<(Vec<String>, usize), String> {
+        match word.get(i + 1) {
+            Some('(') if word.get(i + 2) == Some(&'(') => {
+                Err("arithmetic expansion `$((...))` cannot be evaluated statically".to_string())
+            }
+            Some('(') => Err(COMMAND_SUBSTITUTION.to_string()),
+            Some('{') => {
+                let end = unit_end(word, i);
+                let inner: String = word[i + 2..end.saturating_sub(1).max(i + 2)]
+                    .iter()
+                    .collect();
+                Ok((self.parameter(&inner, line)?, end))
+            }
+            Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                let name = identifier_at(word, i + 1).unwrap_or_default();
+                let end = i + 1 + name.chars().count();
+                let value = self.lookup(&name, line, true)?;
+                Ok((
+                    vec![value.map(|v| v.first().to_string()).unwrap_or_default()],
+                    end,
+                ))
+            }
+            Some(c) if c.is_ascii_digit() || "@*#?$!-".contains(*c) => Err(format!(
+                "special parameter `${}` cannot be evaluated statically",
+                c
+            )),
+            _ => Ok((vec!["$".to_string()], i + 1)),
+        }
+    }
+
+    /// Looks up a variable referenced by an expansion
+    fn lookup(
+        &mut self,
+        name: &str,
+        line: usize,
+        report_unset: bool,
+    ) -> Result<Option<Value>, String> {
+        if self.vars.unresolved.contains_key(name) {
+            return Err(format!(
+                "uses {}, which cannot be evaluated statically",
+                name
+            ));
+        }
+
+        let value = self.vars.values.get(name).cloned();
+        if value.is_none() && report_unset {
+            self.diagnose(
+                line,
+                format!("${} is not set, expanded to an empty string", name),
+            );
+        }
+        Ok(value)
+    }
+
+    /// Evaluates the inside of a `${...}` expansion
+    fn parameter(&mut self, inner: &str, line: usize) -> Result<Vec<String>, String> {
+        let unsupported = || format!("`${{{}}}` cannot be evaluated statically", inner);
+
+        // ${#var} and ${#array[@]}
+        if let Some(rest) = inner.strip_prefix('#').filter(|rest| !rest.is_empty()) {
+            let name =
+                identifier_at(&rest.chars().collect::<Vec<_>>(), 0).ok_or_else(unsupported)?;
+            let value = self.lookup(&name, line, true)?;
+            return match &rest[name.len()..] {
+                "" => Ok(vec![value
+                    .map_or(0, |v| v.first().chars().count())
+                    .to_string()]),
+                "[@]" | "[*]" => Ok(vec![value.map_or(0, |v| v.elements().len()).to_string()]),
+                _ => Err(unsupported()),
+            };
+        }
+
+        let chars: Vec<char> = inner.chars().collect();
+        let name = identifier_at(&chars, 0).ok_or_else(unsupported)?;
+        let mut rest = &inner[name.len()..];
+
+        // An optional subscript: [@], [*] or a literal index
+        let mut index = None;
+        if let Some(subscript) = rest.strip_prefix('[') {
+            let end = subscript.find(']').ok_or_else(unsupported)?;
+            index = Some(&subscript[..end]);
+            rest = &subscript[end + 1..];
+        }
+
+        let has_default = [":-", "-", ":+", "+"].iter().any(|op| rest.starts_with(op));
+        let value = self.lookup(&name, line, !has_default)?;
+        let set = value.is_some();
+        let items: Vec<String> = match (index, &value) {
+            (_, None) => Vec::new(),
+            (None, Some(value)) => vec![value.first().to_string()],
+            (Some("@"), Some(value)) => value.elements(),
+            (Some("*"), Some(value)) => vec![value.elements().join(" ")],
+            (Some(index), Some(value)) => {
+                let index: usize = index.parse().map_err(|_| unsupported())?;
+                value.elements().into_iter().skip(index).take(1).collect()
+            }
+        };
+        let all_empty = items.iter().all(String::is_empty);
+
+        if rest.is_empty() {
+            return Ok(items);
+        }
 
-    Ok(PackageVersion { pkgver, pkgrel })
+        for (op, needs_value) in [(":-", true), ("-", false)] {
+            if let Some(default) = rest.strip_prefix(op) {
+                return if !set || (needs_value && all_empty) {
+                    self.expand_text(default, line).map(|value| vec![value])
+                } else {
+                    Ok(items)
+                };
+            }
+        }
+        for (op, needs_value) in [(":+", true), ("+", false)] {
+            if let Some(alternate) = rest.strip_prefix(op) {
+                return if set && !(needs_value && all_empty) {
+                    self.expand_text(alternate, line).map(|value| vec![value])
+                } else {
+                    Ok(Vec::new())
+                };
+            }
+        }
+
+        if [":=", "=", ":?", "?"].iter().any(|op| rest.starts_with(op)) {
+            return Err(unsupported());
+        }
+
+        for op in ["##", "#", "%%", "%"] {
+            if let Some(pattern) = rest.strip_prefix(op) {
+                let pattern: Vec<char> = self.expand_text(pattern, line)?.chars().collect();
+                return Ok(items
+                    .iter()
+                    .map(|item| remove_match(item, &pattern, op))
+                    .collect());
+            }
+        }
+
+        if let Some(replace) = rest.strip_prefix('/') {
+            let (mode, replace) = match replace.chars().next() {
+                Some(c @ ('/' | '#' | '%')) => (Some(c), &replace[1..]),
+                _ => (None, replace),
+            };
+            let (pattern, replacement) = match replace.find('/') {
+                Some(split) => (&replace[..split], &replace[split + 1..]),
+                None => (replace, ""),
+            };
+            let pattern: Vec<char> = self.expand_text(pattern, line)?.chars().collect();
+            let replacement = self.expand_text(replacement, line)?;
+            return Ok(items
+                .iter()
+                .map(|item| replace_matches(item, &pattern, &replacement, mode))
+                .collect());
+        }
+
+        let case: Option<fn(&str) -> String> = match rest {
+            "^^" => Some(str::to_uppercase),
+            ",," => Some(str::to_lowercase),
+            "^" => Some(|s: &str| map_first(s, char::to_uppercase)),
+            "," => Some(|s: &str| map_first(s, char::to_lowercase)),
+            _ => None,
+        };
+        if let Some(case) = case {
+            return Ok(items.iter().map(|item| case(item)).collect());
+        }
+
+        if let Some(range) = rest.strip_prefix(':') {
+            let mut bounds = range.splitn(2, ':').map(|n| n.trim().parse::<i64>());
+            let offset = bounds.next().and_then(Result::ok).ok_or_else(unsupported)?;
+            let length = bounds
+                .next()
+                .map(|n| n.map_err(|_| unsupported()))
+                .transpose()?;
+            return Ok(items
+                .iter()
+                .map(|item| substring(item, offset, length))
+                .collect());
+        }
+
+        Err(unsupported())
+    }
+
+    /// Expands the text of an expansion operand, e.g. a default value or pattern
+    fn expand_text(&mut self, text: &str, line: usize) -> Result<String, String> {
+        let chars: Vec<char> = text.chars().collect();
+        Ok(self.expand_word(&chars, line, false)?.join(" "))
+    }
+}
+
+const COMMAND_SUBSTITUTION: &str = "command substitution cannot be evaluated statically";
+
+/// Characters that end an unquoted word
+fn is_word_break(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ';' | '&' | '|' | '<' | '>' | '(' | ')')
+}
+
+/// The shell identifier starting at `i`, if any
+fn identifier_at(chars: &[char], i: usize) -> Option<String> {
+    let first = *chars.get(i)?;
+    if !(first.is_ascii_alphabetic() || first == '_') {
+        return None;
+    }
+    Some(
+        chars[i..]
+            .iter()
+            .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+            .collect(),
+    )
+}
+
+/// The variable assigned by the word starting at `i` (`name=` or `name+=`)
+fn assignment_at(chars: &[char], i: usize) -> Option<String> {
+    let name = identifier_at(chars, i)?;
+    let after = i + name.chars().count();
+    match (chars.get(after), chars.get(after + 1)) {
+        (Some('='), _) | (Some('+'), Some('=')) => Some(name),
+        _ => None,
+    }
+}
+
+/// The index after the word unit starting at `i`: a character, an escape,
+/// a quoted string or a `$(...)`/`${...}`/`$'...'` expansion
+fn unit_end(chars: &[char], i: usize) -> usize {
+    let len = chars.len();
+    let quoted_end = |quote: char, mut j: usize| {
+        while j < len {
+            match chars[j] {
+                '\\' => j += 2,
+                c if c == quote => return j + 1,
+                '$' if quote == '"' && matches!(chars.get(j + 1), Some('(' | '{')) => {
+                    j = unit_end(chars, j)
+                }
+                _ => j += 1,
+            }
+        }
+        len
+    };
+
+    match chars[i] {
+        '\\' => (i + 2).min(len),
+        '\'' => chars[i + 1..]
+            .iter()
+            .position(|&c| c == '\'')
+            .map_or(len, |p| i + p + 2),
+        '"' => quoted_end('"', i + 1),
+        '`' => quoted_end('`', i + 1),
+        '$' => match chars.get(i + 1) {
+            Some('(') => unit_end_group(chars, i + 1, '(', ')'),
+            Some('{') => unit_end_group(chars, i + 1, '{', '}'),
+            Some('\'') => quoted_end('\'', i + 2),
+            _ => i + 1,
+        },
+        _ => i + 1,
+    }
+}
+
+/// The index after the balanced group opened at `i`
+fn unit_end_group(chars: &[char], i: usize, open: char, close: char) -> usize {
+    let mut depth = 0;
+    let mut j = i;
+    while j < chars.len() {
+        match chars[j] {
+            c if c == open => {
+                depth += 1;
+                j += 1;
+            }
+            c if c == close => {
+                depth -= 1;
+                j += 1;
+                if depth == 0 {
+                    return j;
+                }
+            }
+            _ => j = unit_end(chars, j),
+        }
+    }
+    chars.len()
+}
+
+/// Appends text to the last field of a word
+fn push_str(fields: &mut [String], text: &str) {
+    if let Some(last) = fields.last_mut() {
+        last.push_str(text);
+    }
+}
+
+/// Appends several fields: the first continues the current one
+fn push_fields(fields: &mut Vec<String>, values: Vec<String>) {
+    let mut values = values.into_iter();
+    if let Some(first) = values.next() {
+        push_str(fields, &first);
+        fields.extend(values);
+    }
+}
+
+/// Decodes the escapes of an ANSI-C quoted string (`$'...'`)
+fn ansi_c(chars: &[char]) -> String {
+    let mut result = String::new();
+    let mut chars = chars.iter();
+    while let Some(&c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some(&c @ ('\\' | '\'' | '"')) => result.push(c),
+            Some(&c) => {
+                result.push('\\');
+                result.push(c);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result
 }
 
-/// Extracts value from a simple bash variable assignment
-fn extract_value(line: &str, prefix: &str) -> String {
-    let value = line[prefix.len()..].trim();
+/// Expands the unquoted braces of an array word, e.g. `foo.{tar.gz,sig}`
+/// or `{1..3}`, into one word per alternative
+///
+/// Fails instead of expanding to more than [`MAX_BRACE_WORDS`] words.
+fn brace_expand(word: &str) -> Result<Vec<String>, String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i] != '{' {
+            i = unit_end(&chars, i);
+            continue;
+        }
+
+        // Find the matching brace and the commas at this level
+        let mut depth = 0;
+        let mut commas = Vec::new();
+        let mut j = i;
+        let mut close = None;
+        while j < chars.len() {
+            match chars[j] {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = Some(j);
+                        break;
+                    }
+                }
+                ',' if depth == 1 => commas.push(j),
+                _ => {}
+            }
+            j = unit_end(&chars, j);
+        }
+        let Some(close) = close else {
+            break;
+        };
+
+        let prefix: String = chars[..i].iter().collect();
+        let suffix: String = chars[close + 1..].iter().collect();
+        let inner: String = chars[i + 1..close].iter().collect();
+        let alternatives: Vec<String> = if commas.is_empty() {
+            match sequence(&inner)? {
+                Some(items) => items,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            }
+        } else {
+            let mut bounds = vec![i];
+            bounds.extend(&commas);
+            bounds.push(close);
+            bounds
+                .windows(2)
+                .map(|w| chars[w[0] + 1..w[1]].iter().collect())
+                .collect()
+        };
+
+        let mut words = Vec::new();
+        for alternative in alternatives {
+            words.extend(brace_expand(&format!(
+                "{}{}{}",
+                prefix, alternative, suffix
+            ))?);
+            if words.len() > MAX_BRACE_WORDS {
+                return Err(too_many_words());
+            }
+        }
+        return Ok(words);
+    }
+
+    Ok(vec![word.to_string()])
+}
 
-    // Remove quotes if present
-    if (value.starts_with('"') && value.ends_with('"'))
-        || (value.starts_with('\'') && value.ends_with('\''))
-    {
-        value[1..value.len() - 1].to_string()
+/// Expands a numeric brace sequence like `1..3`
+/// Returns None if the braces are not a sequence
+fn sequence(inner: &str) -> Result<Option<Vec<String>>, String> {
+    let Some((start, end)) = inner.split_once("..") else {
+        return Ok(None);
+    };
+    let (Ok(start), Ok(end)) = (start.parse::<i64>(), end.parse::<i64>()) else {
+        return Ok(None);
+    };
+    if start.abs_diff(end) >= MAX_BRACE_WORDS as u64 {
+        return Err(too_many_words());
+    }
+    let items: Vec<i64> = if start <= end {
+        (start..=end).collect()
     } else {
-        value.to_string()
+        (end..=start).rev().collect()
+    };
+    Ok(Some(items.iter().map(i64::to_string).collect()))
+}
+
+/// The most words a brace expansion may produce, so a hostile PKGBUILD
+/// cannot exhaust memory with something like `{1..999999999}`
+const MAX_BRACE_WORDS: usize = 4096;
+
+fn too_many_words() -> String {
+    format!(
+        "brace expansion yields more than {} words and is not evaluated",
+        MAX_BRACE_WORDS
+    )
+}
+
+/// Matches text against a shell glob pattern (`*`, `?`, `[...]`, `\x`)
+///
+/// Only the most recent `*` is ever retried, so matching takes at most
+/// pattern length × text length steps instead of backtracking exponentially.
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // The last `*` and the text position it absorbs up to if retried
+    let mut retry: Option<(usize, usize)> = None;
+
+    while p < pattern.len() || t < text.len() {
+        if pattern.get(p) == Some(&'*') {
+            retry = Some((p, t + 1));
+            p += 1;
+            continue;
+        }
+        if let Some(next) = text.get(t).and_then(|&c| match_one(pattern, p, c)) {
+            p = next;
+            t += 1;
+            continue;
+        }
+        match retry {
+            Some((star, next)) if next <= text.len() => {
+                p = star;
+                t = next;
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Matches a character against the pattern element at `p` other than `*`;
+/// returns the index after the element
+fn match_one(pattern: &[char], p: usize, c: char) -> Option<usize> {
+    match pattern.get(p)? {
+        '?' => Some(p + 1),
+        '[' => match class_match(&pattern[p..], Some(c)) {
+            Some((matched, len)) => matched.then_some(p + len),
+            // No closing bracket: a literal `[`
+            None => (c == '[').then_some(p + 1),
+        },
+        '\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        &literal => (literal == c).then_some(p + 1),
+    }
+}
+
+/// Matches a character against the bracket expression at the start of a
+/// pattern; returns whether it matched and the expression's length
+fn class_match(pattern: &[char], c: Option<char>) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let start = i;
+    let mut matched = false;
+    while i < pattern.len() && (pattern[i] != ']' || i == start) {
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&e| e != ']') {
+            matched |= c.is_some_and(|c| pattern[i] <= c && c <= pattern[i + 2]);
+            i += 3;
+        } else {
+            matched |= c == Some(pattern[i]);
+            i += 1;
+        }
+    }
+    if i >= pattern.len() {
+        return None;
     }
+
+    Some((c.is_some() && matched != negated, i + 1))
 }
 
-/// Extracts the package name from a PKGBUILD
-pub fn parse_pkgname(pkgbuild_path: &str) -> Result<String> {
-    let output = Command::new("bash")
-        .arg("-c")
-        .arg(format!(
-            "source '{}' 2>/dev/null && echo \"$pkgname\"",
-            pkgbuild_path
-        ))
-        .output()
-        .context("Failed to execute bash to parse PKGBUILD")?;
+/// Removes the shortest or longest prefix (`#`, `##`) or suffix (`%`, `%%`)
+/// matching a pattern
+fn remove_match(value: &str, pattern: &[char], op: &str) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len();
+    let rest = |range: std::ops::Range<usize>| chars[range].iter().collect::<String>();
 
-    if !output.status.success() {
-        anyhow::bail!("Failed to source PKGBUILD at: {}", pkgbuild_path);
+    match op {
+        "#" => (0..=len)
+            .find(|&i| glob_match(pattern, &chars[..i]))
+            .map(|i| rest(i..len)),
+        "##" => (0..=len)
+            .rev()
+            .find(|&i| glob_match(pattern, &chars[..i]))
+            .map(|i| rest(i..len)),
+        "%" => (0..=len)
+            .rev()
+            .find(|&i| glob_match(pattern, &chars[i..]))
+            .map(|i| rest(0..i)),
+        _ => (0..=len)
+            .find(|&i| glob_match(pattern, &chars[i..]))
+            .map(|i| rest(0..i)),
     }
+    .unwrap_or_else(|| value.to_string())
+}
 
-    let stdout =
-        String::from_utf8(output.stdout).context("Failed to parse bash output as UTF-8")?;
+/// Replaces the first (or with `//`, every) longest match of a pattern;
+/// `#` and `%` anchor the match at the start or end
+fn replace_matches(value: &str, pattern: &[char], replacement: &str, mode: Option<char>) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len();
+    if pattern.is_empty() {
+        return value.to_string();
+    }
 
-    let pkgname = stdout.trim().to_string();
+    match mode {
+        Some('#') => {
+            return match (0..=len).rev().find(|&e| glob_match(pattern, &chars[..e])) {
+                Some(e) => format!("{}{}", replacement, chars[e..].iter().collect::<String>()),
+                None => value.to_string(),
+            }
+        }
+        Some('%') => {
+            return match (0..=len).find(|&s| glob_match(pattern, &chars[s..])) {
+                Some(s) => format!("{}{}", chars[..s].iter().collect::<String>(), replacement),
+                None => value.to_string(),
+            }
+        }
+        _ => {}
+    }
+
+    let mut result = String::new();
+    let mut start = 0;
+    let mut replaced = false;
+    while start < len {
+        let end = if replaced && mode != Some('/') {
+            None
+        } else {
+            (start + 1..=len)
+                .rev()
+                .find(|&e| glob_match(pattern, &chars[start..e]))
+        };
+        match end {
+            Some(end) => {
+                result.push_str(replacement);
+                start = end;
+                replaced = true;
+            }
+            None => {
+                result.push(chars[start]);
+                start += 1;
+            }
+        }
+    }
+    result
+}
 
-    if pkgname.is_empty() {
-        anyhow::bail!("pkgname is empty in PKGBUILD");
+/// Applies a case mapping to the first character
+fn map_first<I: Iterator<Item = char>>(value: &str, map: fn(char) -> I) -> String {
+    let mut chars = value.chars();
+    match chars.next() {
+        Some(first) => map(first).chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// `${var:offset:length}`; negative values count from the end
+/// An offset or length out of range yields an empty string
+fn substring(value: &str, offset: i64, length: Option<i64>) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len() as i64;
+    let start = if offset < 0 {
+        len.saturating_add(offset)
+    } else {
+        offset.min(len)
+    };
+    let end = match length {
+        Some(length) if length < 0 => len.saturating_add(length),
+        Some(length) => start.saturating_add(length).min(len),
+        None => len,
+    };
+    if start < 0 || end < start {
+        return String::new();
     }
+    chars[start as usize..end as usize].iter().collect()
+}
 
-    Ok(pkgname)
+/// Strips a version constraint from a dependency, e.g. `foo>=1.2` becomes `foo`
+pub fn dependency_name(dependency: &str) -> &str {
+    dependency
+        .split(['<', '>', '=', ':'])
+        .next()
+        .unwrap_or(dependency)
+        .trim()
 }
 
 #[cfg(test)]
@@ -148,52 +1497,396 @@ mod tests {
     use tempfile::NamedTempFile;
 
     #[test]
-    fn test_parse_version_simple_basic() {
+    fn test_parse_version_basic() {
         let mut file = NamedTempFile::new().unwrap();
         writeln!(file, "pkgver=1.2.3").unwrap();
         writeln!(file, "pkgrel=1").unwrap();
 
-        let result = parse_version_simple(file.path().to_str().unwrap()).unwrap();
+        let result = parse_version(file.path().to_str().unwrap()).unwrap();
         assert_eq!(result.pkgver, "1.2.3");
         assert_eq!(result.pkgrel, "1");
         assert_eq!(result.to_string(), "1.2.3-1");
     }
 
     #[test]
-    fn test_parse_version_simple_with_quotes() {
+    fn test_parse_version_with_quotes() {
         let mut file = NamedTempFile::new().unwrap();
         writeln!(file, "pkgver=\"1.2.3\"").unwrap();
         writeln!(file, "pkgrel='1'").unwrap();
 
-        let result = parse_version_simple(file.path().to_str().unwrap()).unwrap();
+        let result = parse_version(file.path().to_str().unwrap()).unwrap();
         assert_eq!(result.pkgver, "1.2.3");
         assert_eq!(result.pkgrel, "1");
     }
 
     #[test]
-    fn test_parse_version_simple_with_comments() {
+    fn test_parse_version_with_comments() {
         let mut file = NamedTempFile::new().unwrap();
         writeln!(file, "# This is a comment").unwrap();
         writeln!(file, "pkgver=1.2.3").unwrap();
         writeln!(file, "# Another comment").unwrap();
         writeln!(file, "pkgrel=1").unwrap();
 
-        let result = parse_version_simple(file.path().to_str().unwrap()).unwrap();
+        let result = parse_version(file.path().to_str().unwrap()).unwrap();
         assert_eq!(result.pkgver, "1.2.3");
         assert_eq!(result.pkgrel, "1");
     }
 
+    #[test]
+    fn test_parse_version_str_epoch() {
+        let version = parse_version_str("epoch=2\npkgver=1.2.3\npkgrel=1\n").unwrap();
+        assert_eq!(version.epoch.as_deref(), Some("2"));
+        assert_eq!(version.to_string(), "2:1.2.3-1");
+
+        // A zero epoch is the same as none
+        let zero = parse_version_str("epoch=0\npkgver=1.2.3\npkgrel=1\n").unwrap();
+        let none = parse_version_str("pkgver=1.2.3\npkgrel=1\n").unwrap();
+        assert_eq!(zero, none);
+        assert_eq!(zero.to_string(), "1.2.3-1");
+    }
+
+    #[test]
+    fn test_parse_names_str() {
+        let names = parse_names_str("pkgname=foo\npkgver=1.0\n").unwrap();
+        assert_eq!(names.pkgbase, "foo");
+        assert_eq!(names.pkgnames, vec!["foo"]);
+
+        let content = r#"
+_name=foo
+pkgbase=${_name}
+pkgname=("$pkgbase" "${pkgbase}-docs"
+         # documentation
+         'lib32-foo')
+groups=(desktop 'wayland')
+
+package_foo() {
+    pkgname=ignored
+}
+"#;
+        let names = parse_names_str(content).unwrap();
+        assert_eq!(names.pkgbase, "foo");
+        assert_eq!(names.pkgnames, vec!["foo", "foo-docs", "lib32-foo"]);
+        assert_eq!(names.groups, vec!["desktop", "wayland"]);
+
+        assert!(parse_names_str("pkgver=1.0\n").is_err());
+    }
+
     #[test]
     fn test_parse_version_nonexistent_file() {
-        let result = parse_version_simple("/nonexistent/PKGBUILD");
+        let result = parse_version("/nonexistent/PKGBUILD");
         assert!(result.is_err());
     }
 
     #[test]
-    fn test_extract_value() {
-        assert_eq!(extract_value("pkgver=1.2.3", "pkgver="), "1.2.3");
-        assert_eq!(extract_value("pkgver=\"1.2.3\"", "pkgver="), "1.2.3");
-        assert_eq!(extract_value("pkgver='1.2.3'", "pkgver="), "1.2.3");
-        assert_eq!(extract_value("pkgver=  1.2.3  ", "pkgver="), "1.2.3");
+    fn test_parse_arrays_str() {
+        let content = "pkgname=('foo' 'foo-docs')\n\
+                       depends=('glibc' 'libbar>=1.2')\n\
+                       makedepends=(\n  'cargo'\n)\n";
+
+        let arrays =
+            parse_arrays_str(content, &["pkgname", "depends", "makedepends", "provides"]).unwrap();
+        assert_eq!(arrays[0], vec!["foo", "foo-docs"]);
+        assert_eq!(arrays[1], vec!["glibc", "libbar>=1.2"]);
+        assert_eq!(arrays[2], vec!["cargo"]);
+        assert!(arrays[3].is_empty());
+    }
+
+    #[test]
+    fn test_pkgbuild_from_vars() {
+        let content = r#"pkgbase=foo
+pkgname=(foo foo-docs)
+epoch=1
+pkgver=2.0
+pkgrel=3
+pkgdesc="Foo tool"
+url=https://example.com/foo
+arch=(x86_64 aarch64)
+license=(MIT)
+depends=(glibc)
+optdepends=('bash: completions')
+backup=(etc/foo.conf)
+install=foo.install
+source=("foo-$pkgver.tar.gz")
+sha256sums=(SKIP)
+b2sums=(SKIP)
+source_x86_64=("foo-bin-x86_64.tar.gz")
+sha256sums_x86_64=(abc)
+depends_aarch64=(libfoo-arm)
+source_riscv64=(unlisted.tar.gz)
+"#;
+        let info = Pkgbuild::from_vars(&parse_pkgbuild_str(content)).unwrap();
+
+        assert_eq!(info.pkgbase, "foo");
+        assert_eq!(info.pkgname, vec!["foo", "foo-docs"]);
+        assert_eq!(info.epoch.as_deref(), Some("1"));
+        assert_eq!((info.pkgver.as_str(), info.pkgrel.as_str()), ("2.0", "3"));
+        assert_eq!(info.pkgdesc.as_deref(), Some("Foo tool"));
+        assert_eq!(info.url.as_deref(), Some("https://example.com/foo"));
+        assert_eq!(info.optdepends, vec!["bash: completions"]);
+        assert_eq!(info.install.as_deref(), Some("foo.install"));
+        assert_eq!(info.changelog, None);
+        assert!(info.makedepends.is_empty());
+        assert_eq!(info.source, vec!["foo-2.0.tar.gz"]);
+        assert_eq!(
+            info.checksums.keys().collect::<Vec<_>>(),
+            vec!["b2sums", "sha256sums"]
+        );
+        // Only the architectures the PKGBUILD lists
+        assert_eq!(
+            info.arch_arrays.keys().collect::<Vec<_>>(),
+            vec!["depends_aarch64", "sha256sums_x86_64", "source_x86_64"]
+        );
+
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["sha256sums"][0], "SKIP");
+        assert_eq!(json["source_x86_64"][0], "foo-bin-x86_64.tar.gz");
+
+        // Metadata that cannot be evaluated is an error, not a guess
+        let vars = parse_pkgbuild_str("pkgname=foo\npkgver=1\npkgrel=1\ndepends=($(deps))\n");
+        let error = Pkgbuild::from_vars(&vars).unwrap_err();
+        assert!(error.to_string().starts_with("depends cannot be evaluated"));
+    }
+
+    #[test]
+    fn test_dependency_name() {
+        assert_eq!(dependency_name("libbar>=1.2"), "libbar");
+        assert_eq!(dependency_name("libbar=1.2-1"), "libbar");
+        assert_eq!(dependency_name("libbar<2"), "libbar");
+        assert_eq!(dependency_name("libbar"), "libbar");
+    }
+
+    #[test]
+    fn test_parse_scalar_quoting() {
+        let pkgver = |content: &str| parse_pkgbuild_str(content).scalar("pkgver").unwrap();
+        assert_eq!(pkgver("pkgver=1.2.3").as_deref(), Some("1.2.3"));
+        assert_eq!(pkgver("pkgver=\"1.2.3\"").as_deref(), Some("1.2.3"));
+        assert_eq!(pkgver("pkgver='1.2.3'").as_deref(), Some("1.2.3"));
+        assert_eq!(pkgver("pkgver=1.2.3  # comment").as_deref(), Some("1.2.3"));
+
+        // Like in bash, a space ends the value and the rest is a command
+        let vars = parse_pkgbuild_str("pkgver=  1.2.3  ");
+        assert_eq!(vars.scalar("pkgver").unwrap().as_deref(), Some(""));
+        assert_eq!(
+            vars.diagnostics[0].to_string(),
+            "line 1: command `1.2.3` is not executed"
+        );
+    }
+
+    #[test]
+    fn test_parse_pkgbuild_str() {
+        let content = r#"# Maintainer: Someone <someone@example.com>
+_pkgname=Niri
+pkgname=${_pkgname,,}-git
+pkgver=25.02.r12.g1a2b3c4; pkgrel=1
+_tag="v${pkgver%%.r*}"
+pkgdesc='A scrollable-tiling "Wayland" compositor'
+arch=(x86_64 'aarch64')
+depends=(
+  # runtime
+  cairo "libinput>=1.20" \
+  mesa
+)
+depends+=(pipewire)
+makedepends=("${depends[@]}" rust)
+license=(GPL3)
+license+=MIT
+source=("$_pkgname-${pkgver//./_}.tar.gz"{,.sig} config.{toml,kdl} patch-{1..2}.diff)
+options=($'!lto' ${_unset})
+_url=${_missing:-https://example.com}
+provides=("${pkgname%-git}=${_tag#v}" ${_pkgname^^})
+
+pkgver() {
+  cd "$srcdir/$_pkgname"
+  pkgver=ignored
+  cat > version <<END
+  } ) unbalanced {
+END
+  find . -exec echo {} \;
+}
+
+package() { depends=(ignored); }
+"#;
+        let vars = parse_pkgbuild_str(content);
+        let scalar = |name: &str| vars.scalar(name).unwrap().unwrap();
+        let array = |name: &str| vars.array(name).unwrap();
+
+        assert_eq!(scalar("pkgname"), "niri-git");
+        assert_eq!(scalar("pkgver"), "25.02.r12.g1a2b3c4");
+        assert_eq!(scalar("pkgrel"), "1");
+        assert_eq!(scalar("_tag"), "v25.02");
+        assert_eq!(
+            scalar("pkgdesc"),
+            "A scrollable-tiling \"Wayland\" compositor"
+        );
+        assert_eq!(array("arch"), vec!["x86_64", "aarch64"]);
+        assert_eq!(
+            array("depends"),
+            vec!["cairo", "libinput>=1.20", "mesa", "pipewire"]
+        );
+        assert_eq!(
+            array("makedepends"),
+            vec!["cairo", "libinput>=1.20", "mesa", "pipewire", "rust"]
+        );
+        assert_eq!(array("license"), vec!["GPL3MIT"]);
+        assert_eq!(
+            array("source"),
+            vec![
+                "Niri-25_02_r12_g1a2b3c4.tar.gz",
+                "Niri-25_02_r12_g1a2b3c4.tar.gz.sig",
+                "config.toml",
+                "config.kdl",
+                "patch-1.diff",
+                "patch-2.diff",
+            ]
+        );
+        assert_eq!(array("options"), vec!["!lto"]);
+        assert_eq!(scalar("_url"), "https://example.com");
+        assert_eq!(array("provides"), vec!["niri=25.02", "NIRI"]);
+
+        // Only the unset variable is worth a diagnostic
+        let diagnostics: Vec<String> = vars.diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            diagnostics,
+            vec!["line 18: $_unset is not set, expanded to an empty string"]
+        );
+    }
+
+    #[test]
+    fn test_parse_pkgbuild_unevaluable() {
+        let content = r#"pkgname=foo
+pkgver=$(git describe)
+pkgrel=1
+_commit=`cat commit`
+epoch=$((1 + 1))
+source /etc/makepkg.conf
+if [[ $CARCH == aarch64 ]]; then
+  depends=(foo)
+  arch=(aarch64)
+fi
+[[ $CARCH == x86_64 ]] && makedepends+=(nasm)
+optdepends=("${_commit}: commit")
+backup=(etc/foo.conf)
+license=(MIT)
+license[1]=GPL
+"#;
+        let vars = parse_pkgbuild_str(content);
+
+        assert_eq!(vars.scalar("pkgname").unwrap().as_deref(), Some("foo"));
+        assert_eq!(vars.scalar("pkgrel").unwrap().as_deref(), Some("1"));
+        assert_eq!(vars.array("backup").unwrap(), vec!["etc/foo.conf"]);
+
+        let error = vars.scalar("pkgver").unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "pkgver cannot be evaluated statically (line 2: pkgver: command substitution \
+             cannot be evaluated statically)"
+        );
+        assert!(vars.get("epoch").is_err());
+        assert!(vars.array("depends").is_err());
+        assert!(vars.array("makedepends").is_err());
+        assert!(vars.array("arch").is_err());
+        assert!(vars.array("license").is_err());
+        let error = vars.array("optdepends").unwrap_err().to_string();
+        assert!(error.contains("uses _commit"), "{}", error);
+
+        let diagnostics: Vec<String> = vars.diagnostics.iter().map(|d| d.to_string()).collect();
+        for expected in [
+            "line 6: command `source` is not executed",
+            "line 7: `if` block is not evaluated",
+            "line 8: depends is assigned inside `if`, which is not evaluated",
+            "line 11: command `[[` is not executed",
+            "line 11: makedepends is assigned in a command that is not executed",
+        ] {
+            assert!(
+                diagnostics.contains(&expected.to_string()),
+                "{:?}",
+                diagnostics
+            );
+        }
+
+        // A version that cannot be evaluated is an error, not a guess
+        assert!(parse_version_str(content).is_err());
+    }
+
+    #[test]
+    fn test_parameter_expansion() {
+        let vars = parse_pkgbuild_str(
+            r#"v=1.2.3-beta
+a=${v#*.}
+b=${v##*.}
+c=${v%.*}
+d=${v%%[.-]*}
+e=${v/./_}
+f=${v/#1/x}
+g=${v/%beta/rc}
+h=${v:2:3}
+i=${v: -4}
+j=${#v}
+k=${unset-default}${v:+set}
+l=${v^^}
+"#,
+        );
+        let scalar = |name: &str| vars.scalar(name).unwrap().unwrap();
+
+        assert_eq!(scalar("a"), "2.3-beta");
+        assert_eq!(scalar("b"), "3-beta");
+        assert_eq!(scalar("c"), "1.2");
+        assert_eq!(scalar("d"), "1");
+        assert_eq!(scalar("e"), "1_2.3-beta");
+        assert_eq!(scalar("f"), "x.2.3-beta");
+        assert_eq!(scalar("g"), "1.2.3-rc");
+        assert_eq!(scalar("h"), "2.3");
+        assert_eq!(scalar("i"), "beta");
+        assert_eq!(scalar("j"), "10");
+        assert_eq!(scalar("k"), "defaultset");
+        assert_eq!(scalar("l"), "1.2.3-BETA");
+        assert!(vars.diagnostics.is_empty());
+
+        let vars = parse_pkgbuild_str("a=${!ref}\nb=${v@Q}\n");
+        assert!(vars.get("a").is_err());
+        assert!(vars.get("b").is_err());
+    }
+
+    #[test]
+    fn test_substring_out_of_range() {
+        let vars = parse_pkgbuild_str(
+            r#"x=abc
+a=${x:1:9223372036854775807}
+b=${x: -9223372036854775808}
+c=${x:1:-9223372036854775808}
+d=${x:9223372036854775807}
+e=${x: -2:1}
+"#,
+        );
+        let scalar = |name: &str| vars.scalar(name).unwrap().unwrap();
+
+        assert_eq!(scalar("a"), "bc");
+        assert_eq!(scalar("b"), "");
+        assert_eq!(scalar("c"), "");
+        assert_eq!(scalar("d"), "");
+        assert_eq!(scalar("e"), "b");
+    }
+
+    #[test]
+    fn test_hostile_expansions() {
+        let vars = parse_pkgbuild_str(
+            "source=(x{1..999999999999})\n\
+             depends=({a,b}{a,b}{a,b}{a,b}{a,b}{a,b}{a,b}{a,b}{a,b}{a,b}{a,b}{a,b}{a,b})\n\
+             options=({1..3}{a,b})\n",
+        );
+        let error = vars.array("source").unwrap_err().to_string();
+        assert!(error.contains("more than 4096 words"), "{}", error);
+        assert!(vars.array("depends").is_err());
+        assert_eq!(vars.array("options").unwrap().len(), 6);
+
+        // Would take exponential time with a backtracking matcher
+        let text = format!("x={}\n", "a".repeat(64));
+        let pattern = "*a".repeat(32);
+        let vars = parse_pkgbuild_str(&format!(
+            "{}y=${{x%%{}b}}\nz=${{x##{}}}\n",
+            text, pattern, pattern
+        ));
+        assert_eq!(vars.scalar("y").unwrap().unwrap(), "a".repeat(64));
+        assert_eq!(vars.scalar("z").unwrap().unwrap(), "");
     }
 }
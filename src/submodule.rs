@@ -0,0 +1,986 @@
+use anyhow::{Context, Result};
+use git2::build::{CheckoutBuilder, TreeUpdateBuilder};
+use git2::{
+    Config, FileMode, ObjectType, Oid, Repository, Submodule, SubmoduleIgnore, SubmoduleStatus,
+};
+use std::fs;
+use std::path::Path;
+
+use crate::config::PackagesConfig;
+use crate::git::short_sha;
+use crate::package::{
+    find_all_packages, open_submodule_repo, parse_gitmodules, validate_names, GitmodulesEntry,
+    Package, PackageFilter, SubmoduleRef,
+};
+use crate::pkgbuild;
+
+/// How serious a submodule problem is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found by [`check_submodules`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmoduleIssue {
+    /// Submodule name, or the gitlink path when `.gitmodules` has no entry
+    pub submodule: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl std::fmt::Display for SubmoduleIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.submodule, self.message)
+    }
+}
+
+/// Checks the submodules of a repository for problems
+///
+/// Errors: `.gitmodules` entries and index gitlinks that do not match up,
+/// submodules that are not checked out (their packages are invisible), and
+/// pins that are missing or not on the submodule's branch. Warnings: dirty
+/// checkouts, URLs that differ between `.gitmodules` and `.git/config`, and
+/// pins whose branch cannot be checked.
+pub fn check_submodules(repo_path: &str) -> Result<Vec<SubmoduleIssue>> {
+    let repo = Repository::open(repo_path)
+        .context(format!("Failed to open repository at {}", repo_path))?;
+    let workdir = repo
+        .workdir()
+        .context("Repository has no working directory")?;
+
+    let gitmodules = match fs::read_to_string(workdir.join(".gitmodules")) {
+        Ok(content) => parse_gitmodules(&content),
+        Err(_) => Vec::new(),
+    };
+
+    // Gitlinks recorded in the index, by path
+    let index = repo.index().context("Failed to read the index")?;
+    let gitlinks: Vec<(String, Oid)> = index
+        .iter()
+        .filter(|entry| entry.mode == u32::from(FileMode::Commit))
+        .map(|entry| (String::from_utf8_lossy(&entry.path).to_string(), entry.id))
+        .collect();
+
+    let mut issues = Vec::new();
+    let mut report = |submodule: &str, severity: Severity, message: String| {
+        issues.push(SubmoduleIssue {
+            submodule: submodule.to_string(),
+            severity,
+            message,
+        });
+    };
+
+    for (path, _) in &gitlinks {
+        if !gitmodules.iter().any(|module| module.path == *path) {
+            report(
+                path,
+                Severity::Error,
+                "gitlink in the index has no .gitmodules entry".to_string(),
+            );
+        }
+    }
+
+    for module in &gitmodules {
+        let Some(pin) = gitlinks
+            .iter()
+            .find(|(path, _)| *path == module.path)
+            .map(|(_, id)| *id)
+        else {
+            report(
+                &module.name,
+                Severity::Error,
+                format!(
+                    "listed in .gitmodules, but {} is not in the index",
+                    module.path
+                ),
+            );
+            continue;
+        };
+
+        if !workdir.join(&module.path).join(".git").exists() {
+            report(
+                &module.name,
+                Severity::Error,
+                format!(
+                    "not initialized: {} is not checked out, so its packages are not found \
+                     (run `git submodule update --init`)",
+                    module.path
+                ),
+            );
+        } else {
+            for (severity, message) in check_checkout(&repo, module, pin) {
+                report(&module.name, severity, message);
+            }
+        }
+
+        if let Some(message) = check_url(&repo, module) {
+            report(&module.name, Severity::Warning, message);
+        }
+
+        if let Some((severity, message)) = check_pin(&repo, module, pin) {
+            report(&module.name, severity, message);
+        }
+    }
+
+    issues.sort_by(|a, b| (&a.submodule, a.severity).cmp(&(&b.submodule, b.severity)));
+    Ok(issues)
+}
+
+/// Reports uncommitted changes and a checkout that differs from the pin
+fn check_checkout(
+    repo: &Repository,
+    module: &GitmodulesEntry,
+    pin: Oid,
+) -> Vec<(Severity, String)> {
+    let mut problems = Vec::new();
+    let Ok(status) = repo.submodule_status(&module.name, SubmoduleIgnore::None) else {
+        return problems;
+    };
+
+    if status.contains(SubmoduleStatus::WD_MODIFIED) {
+        let checked_out = repo
+            .find_submodule(&module.name)
+            .ok()
+            .and_then(|submodule| submodule.workdir_id());
+        problems.push((
+            Severity::Warning,
+            format!(
+                "checkout is at {}, but the index pins {}",
+                short_sha(checked_out.map(|oid| oid.to_string()).as_deref()),
+                short_sha(Some(&pin.to_string()))
+            ),
+        ));
+    }
+
+    if status.intersects(
+        SubmoduleStatus::WD_INDEX_MODIFIED
+            | SubmoduleStatus::WD_WD_MODIFIED
+            | SubmoduleStatus::WD_UNTRACKED,
+    ) {
+        problems.push((
+            Severity::Warning,
+            "working tree has uncommitted changes".to_string(),
+        ));
+    }
+
+    problems
+}
+
+/// Compares the `.gitmodules` URL with the one `git submodule init` copied
+/// to `.git/config`
+/// Relative URLs are resolved against the superproject's remote on init,
+/// so they are not compared.
+fn check_url(repo: &Repository, module: &GitmodulesEntry) -> Option<String> {
+    let url = module.url.as_deref()?;
+    if url.starts_with("./") || url.starts_with("../") {
+        return None;
+    }
+
+    let configured = repo
+        .config()
+        .ok()?
+        .get_string(&format!("submodule.{}.url", module.name))
+        .ok()?;
+
+    (configured != url).then(|| {
+        format!(
+            ".gitmodules URL {} differs from .git/config URL {} (run `git submodule sync`)",
+            url, configured
+        )
+    })
+}
+
+/// Checks that the pinned commit exists and is reachable from the branch
+/// the submodule tracks (its remote's default branch unless configured)
+fn check_pin(repo: &Repository, module: &GitmodulesEntry, pin: Oid) -> Option<(Severity, String)> {
+    // Without a store there is nothing to check; the checkout is reported
+    let sub_repo = open_submodule_repo(repo, &module.name)?;
+    let pin_sha = pin.to_string();
+
+    if sub_repo.find_commit(pin).is_err() {
+        return Some((
+            Severity::Error,
+            format!(
+                "pinned commit {} is not in the submodule's object store \
+                 (was it pushed and fetched?)",
+                short_sha(Some(&pin_sha))
+            ),
+        ));
+    }
+
+    // `branch = .` follows the superproject's branch, which a detached
+    // HEAD (as in most CI checkouts) does not have
+    let branch = match module.branch.as_deref() {
+        Some(".") => match superproject_branch(repo) {
+            Ok(branch) => branch,
+            Err(error) => {
+                return Some((
+                    Severity::Warning,
+                    format!("cannot check the pin: {}", error),
+                ))
+            }
+        },
+        Some(branch) => branch.to_string(),
+        None => "HEAD".to_string(),
+    };
+
+    let tip = match sub_repo
+        .find_reference(&format!("refs/remotes/origin/{}", branch))
+        .and_then(|reference| reference.peel_to_commit())
+    {
+        Ok(commit) => commit.id(),
+        Err(_) => {
+            return Some((
+                Severity::Warning,
+                format!(
+                    "cannot check the pin: origin/{} is not fetched in the submodule",
+                    branch
+                ),
+            ))
+        }
+    };
+
+    let reachable = tip == pin || sub_repo.graph_descendant_of(tip, pin).unwrap_or(false);
+    (!reachable).then(|| {
+        (
+            Severity::Error,
+            format!(
+                "pinned commit {} is not on origin/{}",
+                short_sha(Some(&pin_sha)),
+                branch
+            ),
+        )
+    })
+}
+
+/// Adds a package repository as a submodule and stages it
+///
+/// Like `git submodule add`, the submodule is named after its path, which
+/// defaults to `packages/<repository name>`; its package is named after the
+/// directory. Its PKGBUILDs must parse and its package names must not
+/// collide with existing packages; otherwise everything added is removed
+/// again. Returns the packages it provides.
+pub fn add_package(
+    repo_path: &str,
+    url: &str,
+    path: Option<&str>,
+    config: &PackagesConfig,
+) -> Result<Vec<Package>> {
+    let repo = Repository::open(repo_path)
+        .context(format!("Failed to open repository at {}", repo_path))?;
+    let workdir = repo
+        .workdir()
+        .context("Repository has no working directory")?
+        .to_path_buf();
+
+    let path = match path {
+        Some(path) => path.trim_matches('/').to_string(),
+        None => format!("packages/{}", repository_name(url)),
+    };
+    let name = path.rsplit('/').next().unwrap_or(&path).to_string();
+    if name.is_empty() {
+        anyhow::bail!("Cannot derive a package name from {}", url);
+    }
+
+    // Refuse before touching anything
+    let existing = find_all_packages(repo_path, config)?;
+    if let Some(package) = existing.iter().find(|p| p.name == name) {
+        anyhow::bail!("Package {} already exists at {}", name, package.path);
+    }
+    if workdir.join(&path).exists() {
+        anyhow::bail!("{} already exists", path);
+    }
+    let gitmodules_path = workdir.join(".gitmodules");
+    let gitmodules = fs::read_to_string(&gitmodules_path).ok();
+    if let Some(module) = parse_gitmodules(gitmodules.as_deref().unwrap_or_default())
+        .into_iter()
+        .find(|m| m.name == path || m.path == path)
+    {
+        anyhow::bail!(
+            "Submodule {} at {} is already listed in .gitmodules",
+            module.name,
+            module.path
+        );
+    }
+
+    let index_path = repo.path().join("index");
+    let had_index = index_path.exists();
+    let result = clone_submodule(&repo, url, &path).and_then(|mut submodule| {
+        let packages = validate_new_packages(repo_path, &path, config)?;
+        submodule
+            .add_finalize()
+            .context(format!("Failed to stage {}", path))?;
+        Ok(packages)
+    });
+
+    if result.is_err() {
+        // Leave the repository as it was
+        let _ = match &gitmodules {
+            Some(content) => fs::write(&gitmodules_path, content),
+            None => fs::remove_file(&gitmodules_path),
+        };
+        // A repository without commits may not have had an index at all
+        let _ = if had_index {
+            unstage(&repo, &[".gitmodules", &path])
+        } else {
+            fs::remove_file(&index_path).map_err(anyhow::Error::from)
+        };
+        let _ = remove_submodule_files(&repo, &workdir, &path, &path);
+    }
+    result
+}
+
+/// Derives a repository name from a clone URL, e.g. `pkg-niri` from
+/// `https://github.com/hazayan/pkg-niri.git`
+fn repository_name(url: &str) -> &str {
+    let last = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or(url);
+    last.strip_suffix(".git").unwrap_or(last)
+}
+
+/// Sets up and clones a submodule like `git submodule add`: listed in
+/// `.gitmodules` and `.git/config`, with its git directory under
+/// `.git/modules/<path>`
+fn clone_submodule<'r>(repo: &'r Repository, url: &str, path: &str) -> Result<Submodule<'r>> {
+    let mut submodule = repo
+        .submodule(url, Path::new(path), true)
+        .context(format!("Failed to set up submodule {}", path))?;
+    submodule
+        .clone(None)
+        .context(format!("Failed to clone {}", url))?;
+    Ok(submodule)
+}
+
+/// Checks the packages of a newly added submodule: at least one, with
+/// PKGBUILDs the parsers accept and names no other package uses
+fn validate_new_packages(
+    repo_path: &str,
+    name: &str,
+    config: &PackagesConfig,
+) -> Result<Vec<Package>> {
+    let all_packages = find_all_packages(repo_path, config)?;
+    validate_names(&all_packages)?;
+
+    let (added, others): (Vec<Package>, Vec<Package>) = all_packages
+        .into_iter()
+        .partition(|p| p.submodule.as_ref().is_some_and(|s| s.name == name));
+    if added.is_empty() {
+        anyhow::bail!("No PKGBUILD found in {}", name);
+    }
+
+    for package in &added {
+        pkgbuild::parse_version(&package.pkgbuild_path)
+            .context(format!("Invalid PKGBUILD in {}", package.path))?;
+        if package.pkgnames.is_empty() {
+            anyhow::bail!("No pkgname found in {}", package.pkgbuild_path);
+        }
+
+        for pkgname in &package.pkgnames {
+            if let Some(other) = others.iter().find(|p| p.pkgnames.contains(pkgname)) {
+                anyhow::bail!(
+                    "{} in {} is already built by {}",
+                    pkgname,
+                    package.path,
+                    other.path
+                );
+            }
+        }
+    }
+
+    Ok(added)
+}
+
+/// Removes a package and stages the removal
+///
+/// A submodule package is fully deinitialized: its `.gitmodules` and
+/// `.git/config` sections, gitlink, checkout and `.git/modules/<name>` are
+/// removed, even if it was never initialized, along with parent directories
+/// left empty. A direct package's directory
+/// is deleted. The removal is staged before any file is deleted. Returns
+/// the pkgnames no remaining package produces, i.e. those leaving the repo
+/// database.
+pub fn remove_package(repo_path: &str, name: &str, config: &PackagesConfig) -> Result<Vec<String>> {
+    let repo = Repository::open(repo_path)
+        .context(format!("Failed to open repository at {}", repo_path))?;
+    let workdir = repo
+        .workdir()
+        .context("Repository has no working directory")?
+        .to_path_buf();
+
+    let all_packages = find_all_packages(repo_path, config)?;
+    let Some(package) = all_packages.iter().find(|p| p.name == name) else {
+        // An uninitialized submodule has no checkout to discover it from
+        let submodule = find_uninitialized_submodule(&repo, name)
+            .ok_or_else(|| anyhow::anyhow!("Package not found: {}", name))?;
+        remove_submodule(&repo, &workdir, &submodule)?;
+        return Ok(Vec::new());
+    };
+
+    match &package.submodule {
+        Some(submodule) => {
+            // Removing one package of a collection would remove its siblings
+            let siblings: Vec<&str> = all_packages
+                .iter()
+                .filter(|p| p.name != name && p.submodule.as_ref() == Some(submodule))
+                .map(|p| p.name.as_str())
+                .collect();
+            if !siblings.is_empty() {
+                anyhow::bail!(
+                    "{} is part of submodule {} together with {}",
+                    name,
+                    submodule.name,
+                    siblings.join(", ")
+                );
+            }
+            remove_submodule(&repo, &workdir, submodule)?;
+        }
+        None => {
+            let mut index = repo.index().context("Failed to read the index")?;
+            index
+                .remove_dir(Path::new(&package.path), 0)
+                .context(format!("Failed to unstage {}", package.path))?;
+            index.write().context("Failed to write the index")?;
+            remove_dir_if_exists(&workdir.join(&package.path))?;
+            prune_empty_parents(&workdir.join(&package.path), &workdir);
+        }
+    }
+
+    // Another package may still build the same pkgname
+    let remaining: Vec<&str> = all_packages
+        .iter()
+        .filter(|p| p.path != package.path)
+        .flat_map(|p| p.produced_names())
+        .collect();
+    Ok(package
+        .produced_names()
+        .into_iter()
+        .filter(|pkgname| !remaining.contains(pkgname))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Looks up a submodule that is not checked out by its name, path or
+/// package name
+fn find_uninitialized_submodule(repo: &Repository, name: &str) -> Option<SubmoduleRef> {
+    repo.submodules()
+        .ok()?
+        .into_iter()
+        .filter(|submodule| submodule.open().is_err())
+        .find_map(|submodule| {
+            let path = submodule.path().to_string_lossy().to_string();
+            let submodule_name = submodule.name()?;
+            let dir_name = path.rsplit('/').next().unwrap_or(&path);
+            (submodule_name == name || path == name || dir_name == name).then(|| SubmoduleRef {
+                name: submodule_name.to_string(),
+                path: path.clone(),
+            })
+        })
+}
+
+/// Deinitializes a submodule like `git rm` followed by removing its git
+/// directory
+///
+/// The `.gitmodules` change and the gitlink removal are written to the
+/// index first; only then are the `.git/config` entries, the checkout and
+/// the git directory removed.
+fn remove_submodule(repo: &Repository, workdir: &Path, submodule: &SubmoduleRef) -> Result<()> {
+    repo.find_submodule(&submodule.name)
+        .context(format!("Failed to find submodule {}", submodule.name))?;
+
+    let gitmodules_path = workdir.join(".gitmodules");
+    let gitmodules = fs::read_to_string(&gitmodules_path).context("Failed to read .gitmodules")?;
+    let staged = stage_submodule_removal(repo, &gitmodules_path, submodule);
+    if staged.is_err() {
+        let _ = fs::write(&gitmodules_path, &gitmodules);
+    }
+    let gitmodules_left = staged?;
+
+    if !gitmodules_left {
+        fs::remove_file(&gitmodules_path).context("Failed to remove .gitmodules")?;
+    }
+    remove_submodule_files(repo, workdir, &submodule.name, &submodule.path)
+}
+
+/// Removes what a submodule leaves outside the index: its `.git/config`
+/// section, its checkout and its git directory, along with the parent
+/// directories that become empty
+fn remove_submodule_files(repo: &Repository, workdir: &Path, name: &str, path: &str) -> Result<()> {
+    remove_config_section(&repo.path().join("config"), name)
+        .context("Failed to update .git/config")?;
+
+    let checkout = workdir.join(path);
+    remove_dir_if_exists(&checkout)?;
+    prune_empty_parents(&checkout, workdir);
+
+    let git_dir = repo.path().join("modules").join(name);
+    remove_dir_if_exists(&git_dir)?;
+    prune_empty_parents(&git_dir, repo.path());
+    Ok(())
+}
+
+/// Removes a submodule from `.gitmodules` and stages that together with
+/// the removal of its gitlink. Returns whether other submodules are left.
+fn stage_submodule_removal(
+    repo: &Repository,
+    gitmodules_path: &Path,
+    submodule: &SubmoduleRef,
+) -> Result<bool> {
+    remove_config_section(gitmodules_path, &submodule.name)
+        .context("Failed to update .gitmodules")?;
+    let left = !parse_gitmodules(&fs::read_to_string(gitmodules_path)?).is_empty();
+
+    let mut index = repo.index().context("Failed to read the index")?;
+    index
+        .remove_path(Path::new(&submodule.path))
+        .context(format!("Failed to unstage {}", submodule.path))?;
+    if left {
+        index.add_path(Path::new(".gitmodules"))?;
+    } else {
+        index.remove_path(Path::new(".gitmodules"))?;
+    }
+    index.write().context("Failed to write the index")?;
+    Ok(left)
+}
+
+/// Deletes a directory tree, if it is there
+fn remove_dir_if_exists(path: &Path) -> Result<()> {
+    if path.exists() {
+        fs::remove_dir_all(path).context(format!("Failed to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Deletes the directories above `path` that are empty, up to `root`
+fn prune_empty_parents(path: &Path, root: &Path) {
+    for dir in path.ancestors().skip(1) {
+        if dir == root || !dir.starts_with(root) || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
+/// Removes the `[submodule "<name>"]` section from a git config file, as
+/// `git config --remove-section` does
+///
+/// git2 has no such call: the entries are removed through git2, which
+/// leaves the emptied section header behind, and the header is then
+/// filtered out of the file.
+fn remove_config_section(path: &Path, name: &str) -> Result<()> {
+    let prefix = format!("submodule.{}.", name);
+    let mut config = Config::open(path)?;
+    let mut keys = Vec::new();
+    let mut entries = config.entries(None)?;
+    while let Some(entry) = entries.next() {
+        if let Some(key) = entry?.name().filter(|key| key.starts_with(&prefix)) {
+            keys.push(key.to_string());
+        }
+    }
+    drop(entries);
+    for key in keys {
+        config
+            .remove_multivar(&key, ".*")
+            .context(format!("Failed to remove {}", key))?;
+    }
+    drop(config);
+
+    let header = format!(
+        "[submodule \"{}\"]",
+        name.replace('\\', "\\\\").replace('"', "\\\"")
+    );
+    let content = fs::read_to_string(path)?;
+    let filtered: String = content
+        .split_inclusive('\n')
+        .filter(|line| line.trim() != header)
+        .collect();
+    if filtered != content {
+        fs::write(path, filtered)?;
+    }
+    Ok(())
+}
+
+/// Resets index entries to HEAD, removing those HEAD does not have
+fn unstage(repo: &Repository, paths: &[&str]) -> Result<()> {
+    let head = repo
+        .head()
+        .ok()
+        .and_then(|head| head.peel(ObjectType::Commit).ok());
+    repo.reset_default(head.as_ref(), paths)?;
+    Ok(())
+}
+
+/// The outcome of updating one submodule with [`update_submodules`]
+#[derive(Debug, Clone)]
+pub struct PinUpdate {
+    /// Submodule name
+    pub name: String,
+    /// Branch fast-forwarded to, e.g. `origin/main`
+    pub branch: String,
+    /// Pin before the update
+    pub old_commit: String,
+    /// Pin after the update (same as the old one when up to date)
+    pub new_commit: String,
+    /// Version change of each package in the submodule
+    pub versions: Vec<VersionChange>,
+    /// Superproject commit recording the new pin, when requested
+    pub commit: Option<String>,
+}
+
+/// The PKGBUILD version of a package before and after a pin update
+#[derive(Debug, Clone)]
+pub struct VersionChange {
+    pub package: String,
+    pub old_version: Option<String>,
+    pub new_version: Option<String>,
+}
+
+impl std::fmt::Display for PinUpdate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.old_commit == self.new_commit {
+            return write!(
+                f,
+                "{}: up to date with {} ({})",
+                self.name,
+                self.branch,
+                short_sha(Some(&self.new_commit))
+            );
+        }
+
+        write!(
+            f,
+            "{}: {}..{} ({})",
+            self.name,
+            short_sha(Some(&self.old_commit)),
+            short_sha(Some(&self.new_commit)),
+            self.branch
+        )?;
+        for change in &self.versions {
+            write!(
+                f,
+                "\n  {}: {} -> {}",
+                change.package,
+                change.old_version.as_deref().unwrap_or("unknown"),
+                change.new_version.as_deref().unwrap_or("unknown")
+            )?;
+        }
+        if let Some(commit) = &self.commit {
+            write!(f, "\n  committed: {}", short_sha(Some(commit)))?;
+        }
+        Ok(())
+    }
+}
+
+/// Fetches submodules and fast-forwards them to their tracked branch
+///
+/// `names` selects submodules by submodule or package name; all submodule
+/// packages are updated when it is empty. The branch is `branch` in
+/// `.gitmodules` (`.` for the superproject's current branch), else the
+/// remote's default branch. New pins are staged, or
+/// committed one submodule per commit with `commit`. A failing submodule
+/// does not stop the others; failures are returned alongside the updates.
+pub fn update_submodules(
+    repo_path: &str,
+    names: &[String],
+    filter: &PackageFilter,
+    commit: bool,
+    config: &crate::config::Config,
+) -> Result<(Vec<PinUpdate>, Vec<anyhow::Error>)> {
+    let repo = Repository::open(repo_path)
+        .context(format!("Failed to open repository at {}", repo_path))?;
+    let workdir = repo
+        .workdir()
+        .context("Repository has no working directory")?;
+
+    let packages = find_all_packages(repo_path, &config.packages)?;
+    let selector = filter.compile(config, &packages)?;
+    let mut submodules: Vec<&SubmoduleRef> = Vec::new();
+    for submodule in packages.iter().filter_map(|p| p.submodule.as_ref()) {
+        if !submodules.contains(&submodule) {
+            submodules.push(submodule);
+        }
+    }
+
+    // Resolve the selection before changing anything
+    for name in names {
+        let known = submodules.iter().any(|s| s.name == *name)
+            || packages
+                .iter()
+                .any(|p| p.name == *name && p.submodule.is_some());
+        if !known {
+            anyhow::bail!("No submodule package named {}", name);
+        }
+    }
+    submodules.retain(|s| {
+        names.is_empty()
+            || names.iter().any(|name| {
+                s.name == *name
+                    || packages
+                        .iter()
+                        .any(|p| p.name == *name && p.submodule.as_ref() == Some(*s))
+            })
+    });
+    // A submodule is selected when any of its packages is
+    submodules.retain(|s| {
+        packages
+            .iter()
+            .any(|p| p.submodule.as_ref() == Some(*s) && selector.matches(p))
+    });
+
+    let gitmodules =
+        parse_gitmodules(&fs::read_to_string(workdir.join(".gitmodules")).unwrap_or_default());
+
+    let mut updates = Vec::new();
+    let mut failures = Vec::new();
+    for submodule in submodules {
+        let branch = gitmodules
+            .iter()
+            .find(|m| m.name == submodule.name)
+            .and_then(|m| m.branch.clone());
+        let submodule_packages: Vec<&Package> = packages
+            .iter()
+            .filter(|p| p.submodule.as_ref() == Some(submodule))
+            .collect();
+
+        match update_submodule(
+            &repo,
+            submodule,
+            branch.as_deref(),
+            &submodule_packages,
+            commit,
+        ) {
+            Ok(update) => updates.push(update),
+            Err(error) => {
+                failures.push(error.context(format!("Failed to update {}", submodule.name)))
+            }
+        }
+    }
+
+    Ok((updates, failures))
+}
+
+/// The superproject's current branch, which `branch = .` in `.gitmodules`
+/// stands for
+fn superproject_branch(repo: &Repository) -> Result<String> {
+    let head = repo.head().context("Failed to read HEAD")?;
+    if !head.is_branch() {
+        anyhow::bail!("`branch = .` follows the superproject's branch, but HEAD is detached");
+    }
+    head.shorthand()
+        .map(str::to_string)
+        .context("HEAD is not a valid branch name")
+}
+
+/// Fetches one submodule, fast-forwards its checkout and stages (or
+/// commits) the new pin
+fn update_submodule(
+    repo: &Repository,
+    submodule: &SubmoduleRef,
+    branch: Option<&str>,
+    packages: &[&Package],
+    commit: bool,
+) -> Result<PinUpdate> {
+    let mut handle = repo.find_submodule(&submodule.name)?;
+    let sub_repo = handle
+        .open()
+        .context(format!("{} is not checked out", submodule.path))?;
+    let old = handle
+        .index_id()
+        .context(format!("{} has no gitlink in the index", submodule.path))?;
+
+    sub_repo
+        .find_remote("origin")
+        .and_then(|mut remote| remote.fetch(&[] as &[&str], None, None))
+        .context("Failed to fetch origin")?;
+
+    // Without a configured branch, follow the remote's default branch
+    let tracking = match branch {
+        Some(".") => format!("refs/remotes/origin/{}", superproject_branch(repo)?),
+        Some(branch) => format!("refs/remotes/origin/{}", branch),
+        None => sub_repo
+            .find_reference("refs/remotes/origin/HEAD")
+            .ok()
+            .and_then(|head| head.symbolic_target().map(str::to_string))
+            .context("origin/HEAD is not set; set `branch` in .gitmodules")?,
+    };
+    let new = sub_repo
+        .find_reference(&tracking)
+        .and_then(|reference| reference.peel_to_commit())
+        .context(format!("Branch {} not found", tracking))?
+        .id();
+    let branch = tracking
+        .strip_prefix("refs/remotes/")
+        .unwrap_or(&tracking)
+        .to_string();
+
+    let mut update = PinUpdate {
+        name: submodule.name.clone(),
+        branch,
+        old_commit: old.to_string(),
+        new_commit: new.to_string(),
+        versions: Vec::new(),
+        commit: None,
+    };
+    if new == old {
+        return Ok(update);
+    }
+    if !sub_repo.graph_descendant_of(new, old)? {
+        anyhow::bail!(
+            "{} is not a fast-forward of the pinned commit {}",
+            update.branch,
+            short_sha(Some(&update.old_commit))
+        );
+    }
+
+    // Refuses to overwrite local changes in the checkout
+    sub_repo
+        .checkout_tree(
+            sub_repo.find_commit(new)?.as_object(),
+            Some(CheckoutBuilder::new().safe()),
+        )
+        .context("Failed to check out the new commit")?;
+    sub_repo.set_head_detached(new)?;
+    handle
+        .add_to_index(true)
+        .context("Failed to stage the new pin")?;
+
+    for package in packages {
+        let subdir = package.path[submodule.path.len()..].trim_start_matches('/');
+        update.versions.push(VersionChange {
+            package: package.name.clone(),
+            old_version: version_at(&sub_repo, old, subdir),
+            new_version: version_at(&sub_repo, new, subdir),
+        });
+    }
+
+    if commit {
+        update.commit = Some(commit_pin(repo, submodule, new, &update)?.to_string());
+    }
+
+    Ok(update)
+}
+
+/// Reads the PKGBUILD version in a directory of a submodule commit
+fn version_at(sub_repo: &Repository, commit: Oid, subdir: &str) -> Option<String> {
+    let blob = sub_repo
+        .find_commit(commit)
+        .ok()?
+        .tree()
+        .ok()?
+        .get_path(&Path::new(subdir).join("PKGBUILD"))
+        .ok()?
+        .to_object(sub_repo)
+        .ok()?
+        .peel_to_blob()
+        .ok()?;
+
+    pkgbuild::parse_version_str(&String::from_utf8_lossy(blob.content()))
+        .ok()
+        .map(|version| version.to_string())
+}
+
+/// Commits a new pin on top of HEAD, leaving other staged changes staged
+fn commit_pin(
+    repo: &Repository,
+    submodule: &SubmoduleRef,
+    pin: Oid,
+    update: &PinUpdate,
+) -> Result<Oid> {
+    let head = repo.head()?.peel_to_commit()?;
+    let tree_id = TreeUpdateBuilder::new()
+        .upsert(&submodule.path, pin, FileMode::Commit)
+        .create_updated(repo, &head.tree()?)?;
+    let tree = repo.find_tree(tree_id)?;
+
+    let target = match update.versions.as_slice() {
+        [VersionChange {
+            new_version: Some(version),
+            ..
+        }] => version.clone(),
+        _ => short_sha(Some(&update.new_commit)),
+    };
+    let message = format!("Update {} to {}", submodule.name, target);
+
+    let signature = repo
+        .signature()
+        .context("Failed to create a signature (set user.name and user.email)")?;
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        &message,
+        &tree,
+        &[&head],
+    )
+    .context(format!("Failed to commit the update of {}", submodule.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repository_name() {
+        assert_eq!(
+            repository_name("https://github.com/hazayan/pkg-niri.git"),
+            "pkg-niri"
+        );
+        assert_eq!(
+            repository_name("git@github.com:ylabidi/aur-valent.git"),
+            "aur-valent"
+        );
+        assert_eq!(repository_name("file:///tmp/upstream/"), "upstream");
+    }
+
+    #[test]
+    fn test_remove_config_section() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(".gitmodules");
+        let content = r#"[submodule "valent"]
+	path = packages/valent
+	url = git@github.com:ylabidi/aur-valent.git
+[submodule "packages/niri"]
+	path = packages/niri
+	url = https://github.com/hazayan/pkg-niri.git
+	fetchRecurseSubmodules = false
+"#;
+        fs::write(&path, content).unwrap();
+
+        remove_config_section(&path, "valent").unwrap();
+        let left = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            left,
+            &content[content.find("[submodule \"packages").unwrap()..]
+        );
+
+        remove_config_section(&path, "paru").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), left);
+        remove_config_section(&path, "packages/niri").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+    }
+
+    #[test]
+    fn test_prune_empty_parents() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("packages/wm/niri")).unwrap();
+        fs::create_dir_all(root.join("packages/extra")).unwrap();
+        fs::remove_dir(root.join("packages/wm/niri")).unwrap();
+
+        prune_empty_parents(&root.join("packages/wm/niri"), root);
+        assert!(!root.join("packages/wm").exists());
+        assert!(root.join("packages/extra").is_dir());
+    }
+}
@@ -1,3 +1,6 @@
+// The baseline tests pass argument arrays by reference
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use std::fs;
 use std::path::Path;
 use std::process::Command;
@@ -287,7 +290,7 @@ fn test_removed_package_not_reported_as_changed() {
     );
 
     // detect-changes from the recorded base_ref should *not* report the removed package,
-    // documenting the current limitation that deletions are not surfaced as 'changed'
+    // since deletions are only surfaced with `--categories removed`
     let detect_output = Command::new("cargo")
         .args(&[
             "run",
@@ -307,3 +310,2027 @@ fn test_removed_package_not_reported_as_changed() {
         "removed package should not be reported as changed by detect-changes according to current semantics"
     );
 }
+
+/// Helper to run a git command in a directory, asserting success
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(["-c", "protocol.file.allow=always"])
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+/// Helper to stage everything and commit
+fn commit_all(dir: &Path, message: &str) -> String {
+    git(dir, &["add", "-A"]);
+    git(dir, &["commit", "-m", message]);
+    git(dir, &["rev-parse", "HEAD"])
+}
+
+/// Helper to create an upstream package repository to use as a submodule
+fn create_upstream_package(pkgver: &str) -> TempDir {
+    let upstream = create_test_repo();
+    create_pkgbuild(upstream.path(), pkgver, "1");
+    commit_all(upstream.path(), &format!("Release {}", pkgver));
+    upstream
+}
+
+#[test]
+fn test_detect_changes_submodule_log() {
+    let upstream = create_upstream_package("1.0.0");
+    let repo = create_test_repo();
+
+    git(
+        repo.path(),
+        &[
+            "submodule",
+            "add",
+            "--name",
+            "subpkg",
+            upstream.path().to_str().unwrap(),
+            "packages/subpkg",
+        ],
+    );
+    let base_ref = commit_all(repo.path(), "Add subpkg submodule");
+
+    // Two upstream commits, then bump the pin in the superproject
+    create_pkgbuild(upstream.path(), "1.1.0", "1");
+    commit_all(upstream.path(), "Release 1.1.0");
+    create_pkgbuild(upstream.path(), "1.2.0", "1");
+    let new_pin = commit_all(upstream.path(), "Release 1.2.0");
+
+    let sub_dir = repo.path().join("packages").join("subpkg");
+    git(&sub_dir, &["pull", "origin", "HEAD"]);
+    let bump_ref = commit_all(repo.path(), "Bump subpkg");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "detect-changes",
+            "-r",
+            repo.path().to_str().unwrap(),
+            "--base-ref",
+            &base_ref,
+            "--submodule-log",
+            "--format",
+            "json",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(report["packages"], serde_json::json!(["subpkg"]));
+
+    let update = &report["submodules"][0];
+    assert_eq!(update["name"], "subpkg");
+    assert_eq!(update["new_commit"], new_pin.as_str());
+    assert_eq!(update["log_available"], true);
+
+    let summaries: Vec<&str> = update["commits"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["summary"].as_str().unwrap())
+        .collect();
+    assert_eq!(summaries, vec!["Release 1.2.0", "Release 1.1.0"]);
+
+    // The explained change reads versions from the submodule's object store
+    let stdout = detect_changes(
+        repo.path(),
+        &["--base-ref", &base_ref, "--explain", "--format", "json"],
+    );
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let change = &report["packages"][0];
+    assert_eq!(change["reasons"][0]["kind"], "submodule_bumped");
+    assert_eq!(change["reasons"][0]["new_commit"], new_pin.as_str());
+    assert_eq!(change["old_version"], "1.0.0-1");
+    assert_eq!(change["new_version"], "1.2.0-1");
+
+    // A removed submodule is reported without a new pin
+    git(repo.path(), &["rm", "-q", "packages/subpkg"]);
+    commit_all(repo.path(), "Remove subpkg");
+    let stdout = detect_changes(
+        repo.path(),
+        &[
+            "--base-ref",
+            &bump_ref,
+            "--submodule-log",
+            "--format",
+            "json",
+        ],
+    );
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(
+        report["submodules"],
+        serde_json::json!([{
+            "name": "subpkg",
+            "path": "packages/subpkg",
+            "old_commit": new_pin,
+            "new_commit": null,
+            "commits": [],
+            "log_available": true,
+        }])
+    );
+}
+
+/// Helper to run `syspac detect-changes` on a repository with extra arguments
+fn detect_changes(repo: &Path, args: &[&str]) -> String {
+    let output = Command::new("cargo")
+        .args(["run", "--", "detect-changes", "-r", repo.to_str().unwrap()])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "detect-changes failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+/// Helper to create a repo with two packages on `main`, and a `feature`
+/// branch that touches pkg-a while `main` moves on to touch pkg-b
+fn create_diverged_repo() -> TempDir {
+    let repo = create_test_repo();
+    git(repo.path(), &["checkout", "-b", "main"]);
+
+    for name in ["pkg-a", "pkg-b"] {
+        let pkg_dir = repo.path().join(name);
+        fs::create_dir(&pkg_dir).unwrap();
+        create_pkgbuild(&pkg_dir, "1.0.0", "1");
+    }
+    commit_all(repo.path(), "Add packages");
+
+    git(repo.path(), &["checkout", "-b", "feature"]);
+    create_pkgbuild(&repo.path().join("pkg-a"), "1.1.0", "1");
+    commit_all(repo.path(), "Update pkg-a");
+
+    git(repo.path(), &["checkout", "main"]);
+    create_pkgbuild(&repo.path().join("pkg-b"), "1.1.0", "1");
+    commit_all(repo.path(), "Update pkg-b");
+
+    repo
+}
+
+#[test]
+fn test_detect_changes_merge_base() {
+    let repo = create_diverged_repo();
+    git(repo.path(), &["checkout", "feature"]);
+
+    // Diffing directly against main also reports pkg-b, which only changed on main
+    assert_eq!(
+        detect_changes(repo.path(), &["--base-ref", "main"]),
+        "pkg-a pkg-b"
+    );
+
+    // The merge-base only reports what the branch itself changed
+    assert_eq!(
+        detect_changes(repo.path(), &["--merge-base", "main"]),
+        "pkg-a"
+    );
+}
+
+#[test]
+fn test_detect_changes_merge_commit_parents() {
+    let repo = create_diverged_repo();
+    git(
+        repo.path(),
+        &["merge", "--no-ff", "-m", "Merge feature", "feature"],
+    );
+
+    // First parent is main, so only the merged-in package changed
+    assert_eq!(detect_changes(repo.path(), &[]), "pkg-a");
+
+    // Against every parent, the union of both sides is reported
+    assert_eq!(
+        detect_changes(repo.path(), &["--parents", "all"]),
+        "pkg-a pkg-b"
+    );
+}
+
+#[test]
+fn test_detect_changes_explain() {
+    let repo = create_test_repo();
+
+    for name in ["pkg-a", "pkg-b"] {
+        let pkg_dir = repo.path().join(name);
+        fs::create_dir(&pkg_dir).unwrap();
+        create_pkgbuild(&pkg_dir, "1.0.0", "1");
+    }
+    let base_ref = commit_all(repo.path(), "Add packages");
+
+    create_pkgbuild(&repo.path().join("pkg-a"), "1.1.0", "1");
+    let bump = commit_all(repo.path(), "Update pkg-a to 1.1.0");
+    fs::write(repo.path().join("pkg-a").join("fix.patch"), "patch").unwrap();
+    let patch = commit_all(repo.path(), "Add pkg-a patch");
+
+    let stdout = detect_changes(
+        repo.path(),
+        &["--base-ref", &base_ref, "--explain", "--format", "json"],
+    );
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+
+    let packages = report["packages"].as_array().unwrap();
+    assert_eq!(packages.len(), 1);
+
+    let change = &packages[0];
+    assert_eq!(change["name"], "pkg-a");
+    assert_eq!(
+        change["reasons"],
+        serde_json::json!([{"kind": "files_changed"}])
+    );
+    assert_eq!(
+        change["files"],
+        serde_json::json!(["pkg-a/PKGBUILD", "pkg-a/fix.patch"])
+    );
+    assert_eq!(change["old_version"], "1.0.0-1");
+    assert_eq!(change["new_version"], "1.1.0-1");
+
+    let commits: Vec<&str> = change["commits"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(commits, vec![patch.as_str(), bump.as_str()]);
+
+    // Forced packages carry their own reason
+    let stdout = detect_changes(
+        repo.path(),
+        &["--base-ref", &base_ref, "--all", "--explain"],
+    );
+    assert!(stdout.contains("pkg-b (pkg-b)\n  reason: forced by --all"));
+}
+
+#[test]
+fn test_detect_changes_ignore_patterns() {
+    let repo = create_test_repo();
+
+    fs::write(
+        repo.path().join("syspac.toml"),
+        "[ignore]\npatterns = [\"README.md\", \".nvchecker.toml\"]\n\n[ignore.packages]\npkg-b = [\"notes/**\"]\n",
+    )
+    .unwrap();
+    for name in ["pkg-a", "pkg-b"] {
+        let pkg_dir = repo.path().join(name);
+        fs::create_dir(&pkg_dir).unwrap();
+        create_pkgbuild(&pkg_dir, "1.0.0", "1");
+    }
+    let base_ref = commit_all(repo.path(), "Add packages");
+
+    fs::write(repo.path().join("pkg-a").join("README.md"), "docs").unwrap();
+    fs::write(repo.path().join("pkg-b").join(".nvchecker.toml"), "").unwrap();
+    fs::create_dir(repo.path().join("pkg-b").join("notes")).unwrap();
+    fs::write(repo.path().join("pkg-b").join("notes").join("todo"), "").unwrap();
+    commit_all(repo.path(), "Only touch ignored files");
+
+    assert_eq!(detect_changes(repo.path(), &["--base-ref", &base_ref]), "");
+
+    fs::write(repo.path().join("pkg-b").join("fix.patch"), "patch").unwrap();
+    commit_all(repo.path(), "Add pkg-b patch");
+
+    assert_eq!(
+        detect_changes(repo.path(), &["--base-ref", &base_ref]),
+        "pkg-b"
+    );
+}
+
+#[test]
+fn test_detect_changes_with_dependents() {
+    let repo = create_test_repo();
+
+    let packages = [
+        ("libfoo", "provides=('libfoo.so')"),
+        ("foo-cli", "depends=('glibc' 'libfoo>=1.0')"),
+        ("foo-gui", "makedepends=('foo-cli')"),
+        ("unrelated", "depends=('glibc')"),
+    ];
+    for (name, deps) in packages {
+        let pkg_dir = repo.path().join(name);
+        fs::create_dir(&pkg_dir).unwrap();
+        fs::write(
+            pkg_dir.join("PKGBUILD"),
+            format!("pkgname={}\npkgver=1.0.0\npkgrel=1\n{}\n", name, deps),
+        )
+        .unwrap();
+    }
+    let base_ref = commit_all(repo.path(), "Add packages");
+
+    fs::write(repo.path().join("libfoo").join("fix.patch"), "patch").unwrap();
+    commit_all(repo.path(), "Patch libfoo");
+
+    assert_eq!(
+        detect_changes(repo.path(), &["--base-ref", &base_ref]),
+        "libfoo"
+    );
+    assert_eq!(
+        detect_changes(repo.path(), &["--base-ref", &base_ref, "--with-dependents"]),
+        "foo-cli foo-gui libfoo"
+    );
+
+    let stdout = detect_changes(
+        repo.path(),
+        &["--base-ref", &base_ref, "--with-dependents", "--explain"],
+    );
+    assert!(stdout.contains("foo-gui (foo-gui)\n  reason: depends on changed package foo-cli"));
+
+    // The graph comes from the head commit, not from the checkout
+    fs::write(
+        repo.path().join("foo-cli").join("PKGBUILD"),
+        "pkgname=foo-cli\npkgver=1.0.0\npkgrel=1\n",
+    )
+    .unwrap();
+    assert_eq!(
+        detect_changes(repo.path(), &["--base-ref", &base_ref, "--with-dependents"]),
+        "foo-cli foo-gui libfoo"
+    );
+
+    // Local changes read the checkout, also through a relative repo path
+    let output = Command::new("cargo")
+        .args(["run", "--manifest-path"])
+        .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"))
+        .args(["--", "detect-changes", "-r"])
+        .arg(repo.path().file_name().unwrap())
+        .args(["--worktree", "--with-dependents"])
+        .current_dir(repo.path().parent().unwrap())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "detect-changes failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        "foo-cli foo-gui"
+    );
+
+    // A bare clone has no checkout to read PKGBUILDs from
+    let bare = TempDir::new().unwrap();
+    let bare_path = bare.path().join("repo.git");
+    git(
+        bare.path(),
+        &[
+            "clone",
+            "-q",
+            "--bare",
+            repo.path().to_str().unwrap(),
+            bare_path.to_str().unwrap(),
+        ],
+    );
+    assert_eq!(
+        detect_changes(&bare_path, &["--base-ref", &base_ref, "--with-dependents"]),
+        "foo-cli foo-gui libfoo"
+    );
+}
+
+#[test]
+fn test_detect_changes_shared_path_triggers() {
+    let repo = create_test_repo();
+
+    fs::write(
+        repo.path().join("syspac.toml"),
+        r#"
+[[trigger]]
+paths = ["build-container/**"]
+packages = "all"
+
+[[trigger]]
+paths = ["patches/wayland/**"]
+packages = ["pkg-a"]
+"#,
+    )
+    .unwrap();
+    for name in ["pkg-a", "pkg-b"] {
+        let pkg_dir = repo.path().join(name);
+        fs::create_dir(&pkg_dir).unwrap();
+        create_pkgbuild(&pkg_dir, "1.0.0", "1");
+    }
+    fs::create_dir_all(repo.path().join("patches").join("wayland")).unwrap();
+    fs::create_dir(repo.path().join("build-container")).unwrap();
+    fs::write(
+        repo.path().join("build-container").join("Dockerfile"),
+        "FROM a",
+    )
+    .unwrap();
+    let base_ref = commit_all(repo.path(), "Add packages");
+
+    fs::write(
+        repo.path()
+            .join("patches")
+            .join("wayland")
+            .join("fix.patch"),
+        "patch",
+    )
+    .unwrap();
+    let patched = commit_all(repo.path(), "Add shared wayland patch");
+
+    let stdout = detect_changes(repo.path(), &["--base-ref", &base_ref, "--explain"]);
+    assert_eq!(
+        stdout,
+        "pkg-a (pkg-a)\n  reason: shared path patches/wayland/fix.patch matches trigger patches/wayland/**\n  version: 1.0.0-1 -> 1.0.0-1"
+    );
+
+    fs::write(
+        repo.path().join("build-container").join("Dockerfile"),
+        "FROM b",
+    )
+    .unwrap();
+    commit_all(repo.path(), "Update build container");
+
+    assert_eq!(
+        detect_changes(repo.path(), &["--base-ref", &patched]),
+        "pkg-a pkg-b"
+    );
+}
+
+#[test]
+fn test_detect_changes_worktree_and_staged() {
+    let upstream = create_upstream_package("1.0.0");
+    let repo = create_test_repo();
+
+    for name in ["pkg-a", "pkg-b"] {
+        let pkg_dir = repo.path().join(name);
+        fs::create_dir(&pkg_dir).unwrap();
+        create_pkgbuild(&pkg_dir, "1.0.0", "1");
+    }
+    git(
+        repo.path(),
+        &[
+            "submodule",
+            "add",
+            "--name",
+            "subpkg",
+            upstream.path().to_str().unwrap(),
+            "packages/subpkg",
+        ],
+    );
+    commit_all(repo.path(), "Add packages");
+
+    // Stage a version bump for pkg-b, leave pkg-a and the submodule dirty
+    create_pkgbuild(&repo.path().join("pkg-b"), "1.1.0", "1");
+    git(repo.path(), &["add", "pkg-b"]);
+    fs::write(repo.path().join("pkg-a").join("fix.patch"), "patch").unwrap();
+    create_pkgbuild(&repo.path().join("packages").join("subpkg"), "2.0.0", "1");
+
+    assert_eq!(detect_changes(repo.path(), &["--staged"]), "pkg-b");
+    assert_eq!(
+        detect_changes(repo.path(), &["--worktree"]),
+        "pkg-a pkg-b subpkg"
+    );
+
+    let stdout = detect_changes(repo.path(), &["--worktree", "--explain"]);
+    assert!(stdout.contains(
+        "subpkg (packages/subpkg)\n  reason: submodule has uncommitted changes\n  version: 1.0.0-1 -> 2.0.0-1"
+    ));
+    assert!(
+        stdout.contains("pkg-b (pkg-b)\n  reason: files changed\n  version: 1.0.0-1 -> 1.1.0-1")
+    );
+}
+
+#[test]
+fn test_detect_changes_commit_directives() {
+    let repo = create_test_repo();
+
+    for name in ["pkg-a", "pkg-b", "pkg-c"] {
+        let pkg_dir = repo.path().join(name);
+        fs::create_dir(&pkg_dir).unwrap();
+        create_pkgbuild(&pkg_dir, "1.0.0", "1");
+    }
+    let base_ref = commit_all(repo.path(), "Add packages");
+
+    fs::write(repo.path().join("pkg-a").join("fix.patch"), "patch").unwrap();
+    fs::write(repo.path().join("pkg-b").join("fix.patch"), "patch").unwrap();
+    let directive_commit = commit_all(
+        repo.path(),
+        "Patch pkg-a and pkg-b\n\n[rebuild: pkg-c] [skip-build: pkg-b]",
+    );
+
+    assert_eq!(
+        detect_changes(repo.path(), &["--base-ref", &base_ref]),
+        "pkg-a pkg-c"
+    );
+    assert_eq!(
+        detect_changes(repo.path(), &["--base-ref", &base_ref, "--no-directives"]),
+        "pkg-a pkg-b"
+    );
+
+    let stdout = detect_changes(
+        repo.path(),
+        &["--base-ref", &base_ref, "--explain", "--format", "json"],
+    );
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(
+        report["packages"][1]["reasons"][0],
+        serde_json::json!({
+            "kind": "directive",
+            "commit": directive_commit,
+            "directive": "[rebuild: pkg-c]",
+        })
+    );
+    assert_eq!(report["skipped"][0]["name"], "pkg-b");
+
+    git(
+        repo.path(),
+        &[
+            "commit",
+            "--allow-empty",
+            "-m",
+            "Toolchain bump [rebuild-all]",
+        ],
+    );
+    assert_eq!(detect_changes(repo.path(), &[]), "pkg-a pkg-b pkg-c");
+}
+
+#[test]
+fn test_detect_changes_categories() {
+    let repo = create_test_repo();
+
+    for (i, name) in ["keep", "to-remove", "to-rename", "to-move"]
+        .iter()
+        .enumerate()
+    {
+        let pkg_dir = repo.path().join("packages").join(name);
+        fs::create_dir_all(&pkg_dir).unwrap();
+        create_pkgbuild(&pkg_dir, &format!("1.0.{}", i), "1");
+    }
+    let base_ref = commit_all(repo.path(), "Add packages");
+
+    let packages = repo.path().join("packages");
+    create_pkgbuild(&packages.join("keep"), "2.0.0", "1");
+    fs::remove_dir_all(packages.join("to-remove")).unwrap();
+    fs::rename(packages.join("to-rename"), packages.join("renamed")).unwrap();
+    fs::create_dir_all(repo.path().join("extra")).unwrap();
+    fs::rename(
+        packages.join("to-move"),
+        repo.path().join("extra").join("to-move"),
+    )
+    .unwrap();
+    fs::create_dir(packages.join("new-pkg")).unwrap();
+    create_pkgbuild(&packages.join("new-pkg"), "0.1.0", "1");
+    commit_all(repo.path(), "Reshuffle packages");
+
+    let stdout = detect_changes(
+        repo.path(),
+        &[
+            "--base-ref",
+            &base_ref,
+            "--categories",
+            "added,modified,removed,renamed",
+            "--format",
+            "json",
+        ],
+    );
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(
+        report,
+        serde_json::json!({
+            "added": ["new-pkg"],
+            "modified": ["keep"],
+            "removed": ["to-remove"],
+            "renamed": [
+                {"from": "to-rename", "to": "renamed"},
+                {"from": "to-move", "to": "to-move"},
+            ],
+        })
+    );
+
+    // Picking a single category, with paths
+    assert_eq!(
+        detect_changes(
+            repo.path(),
+            &[
+                "--base-ref",
+                &base_ref,
+                "--categories",
+                "removed",
+                "--paths"
+            ],
+        ),
+        "packages/to-remove"
+    );
+
+    // Removed packages stay out of the default output
+    assert_eq!(
+        detect_changes(repo.path(), &["--base-ref", &base_ref]),
+        "keep new-pkg renamed to-move"
+    );
+}
+
+#[test]
+fn test_detect_changes_version_bumps() {
+    let repo = create_test_repo();
+
+    for name in ["refactored", "bumped", "epoch-bumped", "patched"] {
+        let pkg_dir = repo.path().join(name);
+        fs::create_dir(&pkg_dir).unwrap();
+        create_pkgbuild(&pkg_dir, "1.0.0", "1");
+    }
+    let base_ref = commit_all(repo.path(), "Add packages");
+
+    // Same version, different PKGBUILD
+    let pkgbuild = repo.path().join("refactored").join("PKGBUILD");
+    let content = fs::read_to_string(&pkgbuild).unwrap();
+    fs::write(&pkgbuild, content.replace("echo \"test\"", "echo test")).unwrap();
+    fs::write(repo.path().join("patched").join("fix.patch"), "patch").unwrap();
+    create_pkgbuild(&repo.path().join("bumped"), "1.0.0", "2");
+    let pkgbuild = repo.path().join("epoch-bumped").join("PKGBUILD");
+    let content = fs::read_to_string(&pkgbuild).unwrap();
+    fs::write(&pkgbuild, format!("epoch=1\n{}", content)).unwrap();
+    commit_all(repo.path(), "Refactor, patch and bump packages");
+
+    assert_eq!(
+        detect_changes(repo.path(), &["--base-ref", &base_ref]),
+        "bumped epoch-bumped patched refactored"
+    );
+    assert_eq!(
+        detect_changes(repo.path(), &["--base-ref", &base_ref, "--version-bumps"]),
+        "bumped epoch-bumped"
+    );
+
+    let stdout = detect_changes(
+        repo.path(),
+        &["--base-ref", &base_ref, "--version-bumps", "--explain"],
+    );
+    assert!(stdout.contains("version: 1.0.0-1 -> 1:1.0.0-1"));
+}
+
+/// Helper to run syspac list-packages, asserting success
+fn list_packages(repo: &Path, args: &[&str]) -> String {
+    let output = Command::new("cargo")
+        .args(["run", "--", "list-packages", "-r", repo.to_str().unwrap()])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "list-packages failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+#[test]
+fn test_list_packages_at_ref_and_bare_clone() {
+    let upstream = create_upstream_package("1.0.0");
+    let repo = create_test_repo();
+
+    let pkg_dir = repo.path().join("packages").join("pkg-a");
+    fs::create_dir_all(&pkg_dir).unwrap();
+    create_pkgbuild(&pkg_dir, "1.0.0", "1");
+    git(
+        repo.path(),
+        &[
+            "submodule",
+            "add",
+            upstream.path().to_str().unwrap(),
+            "packages/upstream",
+        ],
+    );
+    let base_ref = commit_all(repo.path(), "Add packages");
+    git(repo.path(), &["tag", "v1"]);
+
+    create_pkgbuild(&pkg_dir, "2.0.0", "1");
+    fs::remove_dir_all(repo.path().join("packages").join("upstream")).unwrap();
+    git(repo.path(), &["rm", "-q", "--cached", "packages/upstream"]);
+    git(
+        repo.path(),
+        &[
+            "config",
+            "-f",
+            ".gitmodules",
+            "--remove-section",
+            "submodule.packages/upstream",
+        ],
+    );
+    let pkg_b = repo.path().join("packages").join("pkg-b");
+    fs::create_dir_all(&pkg_b).unwrap();
+    create_pkgbuild(&pkg_b, "0.1.0", "1");
+    commit_all(repo.path(), "Drop the submodule, bump pkg-a, add pkg-b");
+
+    assert_eq!(list_packages(repo.path(), &[]), "pkg-a\npkg-b");
+    assert_eq!(
+        list_packages(repo.path(), &["--at", "v1", "--verbose"]),
+        "pkg-a: 1.0.0-1\nupstream: 1.0.0-1"
+    );
+
+    // A bare clone has no working directory at all
+    let bare = TempDir::new().unwrap();
+    let bare_path = bare.path().join("repo.git");
+    git(
+        bare.path(),
+        &[
+            "clone",
+            "-q",
+            "--bare",
+            repo.path().to_str().unwrap(),
+            bare_path.to_str().unwrap(),
+        ],
+    );
+
+    assert_eq!(
+        list_packages(&bare_path, &["--verbose"]),
+        "pkg-a: 2.0.0-1\npkg-b: 0.1.0-1"
+    );
+    assert_eq!(
+        detect_changes(&bare_path, &["--base-ref", &base_ref]),
+        "pkg-a pkg-b"
+    );
+}
+
+#[test]
+fn test_detect_changes_missing_base() {
+    let origin = create_test_repo();
+    for name in ["pkg-a", "pkg-b"] {
+        let pkg_dir = origin.path().join(name);
+        fs::create_dir(&pkg_dir).unwrap();
+        create_pkgbuild(&pkg_dir, "1.0.0", "1");
+    }
+    let base_ref = commit_all(origin.path(), "Add packages");
+    create_pkgbuild(&origin.path().join("pkg-a"), "1.1.0", "1");
+    commit_all(origin.path(), "Bump pkg-a");
+    create_pkgbuild(&origin.path().join("pkg-a"), "1.2.0", "1");
+    commit_all(origin.path(), "Bump pkg-a again");
+
+    let url = format!("file://{}", origin.path().display());
+    let clones = TempDir::new().unwrap();
+    let shallow = clones.path().join("shallow");
+    git(
+        clones.path(),
+        &[
+            "clone",
+            "-q",
+            "--depth",
+            "1",
+            &url,
+            shallow.to_str().unwrap(),
+        ],
+    );
+
+    let run = |args: &[&str]| {
+        Command::new("cargo")
+            .args([
+                "run",
+                "--",
+                "detect-changes",
+                "-r",
+                shallow.to_str().unwrap(),
+            ])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    // Neither HEAD^ nor the base ref are in a depth-1 clone
+    let output = run(&[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--missing-base"));
+    assert!(!run(&["--base-ref", &base_ref]).status.success());
+
+    let output = run(&["--missing-base", "all", "--explain"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("pkg-b (pkg-b)\n  reason: base commit not available"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("selecting all packages"));
+
+    // The fallback is taken once, also when submodule pins are reported
+    let output = run(&["--missing-base", "all", "--submodule-log"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        stderr.matches("selecting all packages").count(),
+        1,
+        "{}",
+        stderr
+    );
+
+    // No build recorded yet
+    assert!(
+        !run(&["--base-ref", &base_ref, "--missing-base", "last-build"])
+            .status
+            .success()
+    );
+
+    git(&shallow, &["config", "user.email", "test@example.com"]);
+    git(&shallow, &["config", "user.name", "Test User"]);
+    let built = Command::new("cargo")
+        .args(["run", "--", "mark-built", "-r", shallow.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(built.status.success());
+    create_pkgbuild(&shallow.join("pkg-b"), "1.0.0", "2");
+    commit_all(&shallow, "Rebuild pkg-b");
+
+    let output = run(&["--base-ref", &base_ref, "--missing-base", "last-build"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "pkg-b");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("comparing against last build"));
+
+    // Fetching the history makes the real base available again
+    let output = run(&["--base-ref", &base_ref, "--missing-base", "deepen"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        "pkg-a pkg-b"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("fetched missing history from origin"));
+    assert_eq!(
+        detect_changes(&shallow, &["--base-ref", &base_ref]),
+        "pkg-a pkg-b"
+    );
+
+    // HEAD^ is found again once the clone is unshallowed
+    let fresh = clones.path().join("fresh");
+    git(
+        clones.path(),
+        &["clone", "-q", "--depth", "1", &url, fresh.to_str().unwrap()],
+    );
+    assert_eq!(
+        detect_changes(&fresh, &["--missing-base", "deepen"]),
+        "pkg-a"
+    );
+}
+
+#[test]
+fn test_detect_changes_github_events() {
+    let repo = create_diverged_repo();
+    let main_tip = git(repo.path(), &["rev-parse", "main"]);
+    let feature_tip = git(repo.path(), &["rev-parse", "feature"]);
+    let initial = git(repo.path(), &["rev-parse", "main~1"]);
+
+    // Several commits pushed at once: HEAD^ would only see the last one
+    create_pkgbuild(&repo.path().join("pkg-b"), "1.2.0", "1");
+    let pushed = commit_all(repo.path(), "Update pkg-b again");
+    fs::write(repo.path().join("notes.txt"), "notes").unwrap();
+    commit_all(repo.path(), "Unrelated commit after the push");
+
+    let events = TempDir::new().unwrap();
+    let write_event = |name: &str, payload: String| {
+        let path = events.path().join(name);
+        fs::write(&path, payload).unwrap();
+        path.to_str().unwrap().to_string()
+    };
+
+    let push = write_event(
+        "push.json",
+        format!(
+            r#"{{"ref": "refs/heads/main", "before": "{}", "after": "{}", "forced": false}}"#,
+            initial, pushed
+        ),
+    );
+    assert_eq!(detect_changes(repo.path(), &["--event", &push]), "pkg-b");
+
+    let pull_request = write_event(
+        "pull_request.json",
+        format!(
+            r#"{{"action": "opened", "pull_request": {{"base": {{"sha": "{}"}}, "head": {{"sha": "{}"}}}}}}"#,
+            main_tip, feature_tip
+        ),
+    );
+    assert_eq!(
+        detect_changes(repo.path(), &["--event", &pull_request]),
+        "pkg-a"
+    );
+
+    // A new branch is compared against the default branch
+    let new_branch = write_event(
+        "new_branch.json",
+        format!(
+            r#"{{"before": "0000000000000000000000000000000000000000", "after": "{}",
+                "repository": {{"default_branch": "main"}}}}"#,
+            feature_tip
+        ),
+    );
+    assert_eq!(
+        detect_changes(repo.path(), &["--event", &new_branch]),
+        "pkg-a"
+    );
+
+    // A forced push whose old tip is gone falls back the same way
+    let forced = write_event(
+        "forced.json",
+        format!(
+            r#"{{"before": "1111111111111111111111111111111111111111", "after": "{}",
+                "forced": true, "repository": {{"default_branch": "main"}}}}"#,
+            feature_tip
+        ),
+    );
+    assert_eq!(detect_changes(repo.path(), &["--event", &forced]), "pkg-a");
+}
+
+#[test]
+fn test_repository_config() {
+    let repo = create_test_repo();
+
+    for path in ["pkgs/pkg-a", "vendor/pkg-b", "pkgs/wip"] {
+        let pkg_dir = repo.path().join(path);
+        fs::create_dir_all(&pkg_dir).unwrap();
+        create_pkgbuild(&pkg_dir, "1.0.0", "1");
+    }
+    fs::write(
+        repo.path().join("syspac.toml"),
+        r#"
+[repo]
+name = "custom"
+architectures = ["x86_64", "aarch64"]
+signing_key = "ABCDEF"
+
+[release]
+tag = "packages"
+
+[packages]
+roots = ["pkgs"]
+exclude = ["wip"]
+"#,
+    )
+    .unwrap();
+    commit_all(repo.path(), "Add packages");
+
+    assert_eq!(list_packages(repo.path(), &[]), "pkg-a");
+
+    let config = |args: &[&str]| {
+        Command::new("cargo")
+            .args(["run", "--", "config", "-r", repo.path().to_str().unwrap()])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let output = config(&["repo.name"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "custom");
+    let output = config(&["repo.architectures"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        "x86_64 aarch64"
+    );
+    let output = config(&["repo.signing_key"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "ABCDEF");
+    let output = config(&["packages.roots"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "pkgs");
+    let output = config(&["release.tag"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "packages");
+    assert!(!config(&["repo.colour"]).status.success());
+
+    // Unknown keys are rejected by every command
+    fs::write(
+        repo.path().join("syspac.toml"),
+        "[packages]\nroot = [\"pkgs\"]\n",
+    )
+    .unwrap();
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "list-packages",
+            "-r",
+            repo.path().to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown field `root`"));
+}
+
+#[test]
+fn test_package_roots_depth_and_collections() {
+    let repo = create_test_repo();
+
+    for path in ["packages/core/pkg-a", "packages/extra/pkg-b"] {
+        let pkg_dir = repo.path().join(path);
+        fs::create_dir_all(&pkg_dir).unwrap();
+        create_pkgbuild(&pkg_dir, "1.0.0", "1");
+    }
+
+    // A submodule holding several packages
+    let collection = create_test_repo();
+    for name in ["pkg-c", "pkg-d"] {
+        let pkg_dir = collection.path().join(name);
+        fs::create_dir_all(&pkg_dir).unwrap();
+        create_pkgbuild(&pkg_dir, "0.1.0", "1");
+    }
+    commit_all(collection.path(), "Add packages");
+    git(
+        repo.path(),
+        &[
+            "submodule",
+            "add",
+            "--name",
+            "collection",
+            collection.path().to_str().unwrap(),
+            "vendor/collection",
+        ],
+    );
+    commit_all(repo.path(), "Add packages");
+
+    // The default scan stops two levels below the repository root
+    assert_eq!(list_packages(repo.path(), &[]), "pkg-c\npkg-d");
+
+    fs::write(
+        repo.path().join("syspac.toml"),
+        "[packages]\nroots = [\"packages/*\"]\nmax_depth = 1\n",
+    )
+    .unwrap();
+    commit_all(repo.path(), "Configure package roots");
+
+    assert_eq!(
+        list_packages(repo.path(), &["--verbose"]),
+        "pkg-a: 1.0.0-1\npkg-b: 1.0.0-1\npkg-c: 0.1.0-1\npkg-d: 0.1.0-1"
+    );
+    assert_eq!(
+        list_packages(repo.path(), &["--at", "HEAD", "--verbose"]),
+        "pkg-a: 1.0.0-1\npkg-b: 1.0.0-1\npkg-c: 0.1.0-1\npkg-d: 0.1.0-1"
+    );
+
+    // Ignored directories are skipped in the working directory
+    fs::write(
+        repo.path().join("syspac.toml"),
+        "[packages]\nmax_depth = 3\n",
+    )
+    .unwrap();
+    fs::write(repo.path().join(".gitignore"), "scratch/\n").unwrap();
+    let scratch = repo.path().join("scratch").join("pkg-e");
+    fs::create_dir_all(&scratch).unwrap();
+    create_pkgbuild(&scratch, "1.0.0", "1");
+    let base_ref = commit_all(repo.path(), "Scan three levels deep");
+
+    assert_eq!(
+        list_packages(repo.path(), &[]),
+        "pkg-a\npkg-b\npkg-c\npkg-d"
+    );
+
+    // Bumping the submodule changes the packages inside it
+    create_pkgbuild(&collection.path().join("pkg-c"), "0.2.0", "1");
+    commit_all(collection.path(), "Release pkg-c 0.2.0");
+    let sub_dir = repo.path().join("vendor").join("collection");
+    git(&sub_dir, &["pull", "-q", "origin", "HEAD"]);
+    commit_all(repo.path(), "Bump collection");
+
+    let stdout = detect_changes(
+        repo.path(),
+        &["--base-ref", &base_ref, "--explain", "--format", "json"],
+    );
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(report["packages"].as_array().unwrap().len(), 1);
+    let change = &report["packages"][0];
+    assert_eq!(change["name"], "pkg-c");
+    assert_eq!(change["reasons"][0]["kind"], "submodule_bumped");
+    assert_eq!(change["old_version"], "0.1.0-1");
+    assert_eq!(change["new_version"], "0.2.0-1");
+}
+
+#[test]
+fn test_list_packages_split_pkgnames() {
+    let repo = create_test_repo();
+
+    let pkg_dir = repo.path().join("packages").join("foo");
+    fs::create_dir_all(&pkg_dir).unwrap();
+    fs::write(
+        pkg_dir.join("PKGBUILD"),
+        r#"pkgbase=foo
+pkgname=("${pkgbase}" "${pkgbase}-docs")
+pkgver=1.0.0
+pkgrel=1
+
+package_foo() {
+    :
+}
+
+package_foo-docs() {
+    :
+}
+"#,
+    )
+    .unwrap();
+    let bar_dir = repo.path().join("packages").join("bar");
+    fs::create_dir_all(&bar_dir).unwrap();
+    create_pkgbuild(&bar_dir, "2.0.0", "1");
+    commit_all(repo.path(), "Add a split package");
+
+    assert_eq!(list_packages(repo.path(), &[]), "bar\nfoo");
+    assert_eq!(
+        list_packages(repo.path(), &["--pkgnames"]),
+        "test-package\nfoo\nfoo-docs"
+    );
+    assert_eq!(
+        list_packages(repo.path(), &["--pkgnames", "--verbose", "--at", "HEAD"]),
+        "test-package: 2.0.0-1\nfoo: 1.0.0-1\nfoo-docs: 1.0.0-1"
+    );
+}
+
+#[test]
+fn test_duplicate_and_mismatched_names() {
+    let repo = create_test_repo();
+
+    for path in ["packages/wm/niri", "packages/extra/ly"] {
+        let pkg_dir = repo.path().join(path);
+        fs::create_dir_all(&pkg_dir).unwrap();
+        fs::write(
+            pkg_dir.join("PKGBUILD"),
+            format!(
+                "pkgname={}\npkgver=1.0.0\npkgrel=1\n",
+                path.rsplit('/').next().unwrap()
+            ),
+        )
+        .unwrap();
+    }
+    let paru = create_upstream_package("2.0.0");
+    fs::write(
+        paru.path().join("PKGBUILD"),
+        "pkgname=paru-bin\npkgver=2.0.0\npkgrel=1\n",
+    )
+    .unwrap();
+    commit_all(paru.path(), "Rename to paru-bin");
+    git(
+        repo.path(),
+        &[
+            "submodule",
+            "add",
+            "--name",
+            "paru",
+            paru.path().to_str().unwrap(),
+            "packages/aur/paru",
+        ],
+    );
+    fs::write(
+        repo.path().join("syspac.toml"),
+        "[packages]\nmax_depth = 3\n",
+    )
+    .unwrap();
+    let base_ref = commit_all(repo.path(), "Add packages");
+
+    let run = |command: &str, args: &[&str]| {
+        Command::new("cargo")
+            .args(["run", "--", command, "-r", repo.path().to_str().unwrap()])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    // A mismatched pkgbase is only a warning
+    let output = run("list-packages", &[]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        "ly\nniri\nparu"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "warning: packages/aur/paru: .gitmodules name `paru`, directory `paru`, \
+         pkgbase `paru-bin` disagree"
+    ));
+
+    // Two directories with the same name are an error, before any output
+    let dup_dir = repo.path().join("packages").join("extra").join("niri");
+    fs::create_dir_all(&dup_dir).unwrap();
+    fs::write(
+        dup_dir.join("PKGBUILD"),
+        "pkgname=niri\npkgver=1\npkgrel=1\n",
+    )
+    .unwrap();
+    commit_all(repo.path(), "Add a second niri");
+
+    for (command, args) in [
+        ("list-packages", vec![]),
+        ("detect-changes", vec!["--base-ref", base_ref.as_str()]),
+    ] {
+        let output = run(command, &args);
+        assert!(!output.status.success(), "{} succeeded", command);
+        assert!(output.stdout.is_empty());
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .contains("Duplicate package name niri: packages/extra/niri, packages/wm/niri"));
+    }
+}
+
+#[test]
+fn test_check_submodules() {
+    let good = create_upstream_package("1.0.0");
+    let other = create_upstream_package("1.0.0");
+    let repo = create_test_repo();
+    for (name, upstream) in [("good", &good), ("other", &other)] {
+        git(
+            repo.path(),
+            &[
+                "submodule",
+                "add",
+                "--name",
+                name,
+                upstream.path().to_str().unwrap(),
+                &format!("packages/{}", name),
+            ],
+        );
+    }
+    commit_all(repo.path(), "Add submodules");
+
+    let check = |repo: &Path, args: &[&str]| {
+        let output = Command::new("cargo")
+            .args([
+                "run",
+                "--",
+                "check-submodules",
+                "-r",
+                repo.to_str().unwrap(),
+            ])
+            .args(args)
+            .output()
+            .unwrap();
+        (
+            output.status.success(),
+            String::from_utf8(output.stdout).unwrap().trim().to_string(),
+        )
+    };
+    assert_eq!(check(repo.path(), &[]), (true, String::new()));
+
+    // Warnings alone pass unless --strict
+    let other_dir = repo.path().join("packages").join("other");
+    fs::write(other_dir.join("notes.txt"), "wip").unwrap();
+    git(
+        repo.path(),
+        &[
+            "config",
+            "-f",
+            ".gitmodules",
+            "submodule.good.url",
+            "https://example.com/good.git",
+        ],
+    );
+    let expected = format!(
+        "warning: good: .gitmodules URL https://example.com/good.git differs from \
+         .git/config URL {} (run `git submodule sync`)\n\
+         warning: other: working tree has uncommitted changes",
+        good.path().to_str().unwrap()
+    );
+    assert_eq!(check(repo.path(), &[]), (true, expected.clone()));
+    assert_eq!(check(repo.path(), &["--strict"]), (false, expected));
+    fs::remove_file(other_dir.join("notes.txt")).unwrap();
+    git(repo.path(), &["checkout", "--", ".gitmodules"]);
+
+    // A pin that only exists locally is not on the upstream branch
+    git(&other_dir, &["config", "user.name", "Test User"]);
+    git(&other_dir, &["config", "user.email", "test@example.com"]);
+    create_pkgbuild(&other_dir, "1.1.0", "1");
+    let local_pin = commit_all(&other_dir, "Local change");
+    commit_all(repo.path(), "Pin an unpushed commit");
+    let (ok, output) = check(repo.path(), &[]);
+    assert!(!ok);
+    assert_eq!(
+        output,
+        format!(
+            "error: other: pinned commit {} is not on origin/HEAD",
+            &local_pin[..7]
+        )
+    );
+
+    // `branch = .` cannot be checked on a detached HEAD, as in CI checkouts
+    git(
+        repo.path(),
+        &["config", "-f", ".gitmodules", "submodule.good.branch", "."],
+    );
+    commit_all(repo.path(), "Track the superproject's branch");
+    git(repo.path(), &["checkout", "-q", "--detach"]);
+    let (ok, output) = check(repo.path(), &[]);
+    assert!(!ok);
+    assert_eq!(
+        output,
+        format!(
+            "warning: good: cannot check the pin: `branch = .` follows the superproject's \
+             branch, but HEAD is detached\n\
+             error: other: pinned commit {} is not on origin/HEAD",
+            &local_pin[..7]
+        )
+    );
+
+    // The repository configuration is validated as by every command
+    fs::write(
+        repo.path().join("syspac.toml"),
+        "[repo]\ncolour = \"red\"\n",
+    )
+    .unwrap();
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "check-submodules",
+            "-r",
+            repo.path().to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown field `colour`"));
+    fs::remove_file(repo.path().join("syspac.toml")).unwrap();
+
+    // A fresh clone without --recurse-submodules has no checkouts, and a
+    // .gitmodules entry without a gitlink is inconsistent
+    let clone = TempDir::new().unwrap();
+    git(
+        clone.path(),
+        &["clone", "-q", repo.path().to_str().unwrap(), "."],
+    );
+    git(
+        clone.path(),
+        &[
+            "config",
+            "-f",
+            ".gitmodules",
+            "submodule.ghost.path",
+            "packages/ghost",
+        ],
+    );
+    let (ok, output) = check(clone.path(), &[]);
+    assert!(!ok);
+    assert_eq!(
+        output,
+        "error: ghost: listed in .gitmodules, but packages/ghost is not in the index\n\
+         error: good: not initialized: packages/good is not checked out, so its packages \
+         are not found (run `git submodule update --init`)\n\
+         error: other: not initialized: packages/other is not checked out, so its packages \
+         are not found (run `git submodule update --init`)"
+    );
+}
+
+#[test]
+fn test_add_package() {
+    let repo = create_test_repo();
+    let pkg_dir = repo.path().join("packages").join("pkg-a");
+    fs::create_dir_all(&pkg_dir).unwrap();
+    fs::write(
+        pkg_dir.join("PKGBUILD"),
+        "pkgname=pkg-a\npkgver=1.0.0\npkgrel=1\n",
+    )
+    .unwrap();
+    commit_all(repo.path(), "Add pkg-a");
+
+    let upstream = create_test_repo();
+    fs::write(
+        upstream.path().join("PKGBUILD"),
+        "pkgname=niri\npkgver=25.02\npkgrel=1\n",
+    )
+    .unwrap();
+    commit_all(upstream.path(), "Release 25.02");
+    let url = format!("file://{}", upstream.path().display());
+
+    let add = |args: &[&str]| {
+        Command::new("cargo")
+            .args([
+                "run",
+                "--",
+                "add-package",
+                "-r",
+                repo.path().to_str().unwrap(),
+            ])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let output = add(&[&url, "--path", "packages/niri"]);
+    assert!(
+        output.status.success(),
+        "add-package failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        "niri (packages/niri): 25.02-1"
+    );
+
+    // Staged, named after the directory, with its git directory absorbed
+    let staged = git(repo.path(), &["diff", "--cached", "--name-only"]);
+    assert_eq!(staged, ".gitmodules\npackages/niri");
+    assert_eq!(
+        git(
+            repo.path(),
+            &["config", "-f", ".gitmodules", "submodule.packages/niri.url"]
+        ),
+        url
+    );
+    assert!(repo.path().join(".git/modules/packages/niri").is_dir());
+    assert_eq!(list_packages(repo.path(), &[]), "niri\npkg-a");
+    assert!(String::from_utf8(
+        Command::new("cargo")
+            .args([
+                "run",
+                "--",
+                "check-submodules",
+                "-r",
+                repo.path().to_str().unwrap()
+            ])
+            .output()
+            .unwrap()
+            .stdout
+    )
+    .unwrap()
+    .trim()
+    .is_empty());
+    commit_all(repo.path(), "Add niri");
+
+    // Name collisions are refused and leave nothing behind
+    let before = snapshot(repo.path());
+    let output = add(&[&url, "--path", "vendor/pkg-a"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Package pkg-a already exists at packages/pkg-a"));
+
+    let output = add(&[&url, "--path", "vendor/niri-git"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("niri in vendor/niri-git is already built by packages/niri"));
+    assert_eq!(snapshot(repo.path()), before);
+    assert_eq!(git(repo.path(), &["status", "--porcelain"]), "");
+
+    // So is a repository without a PKGBUILD, with the first submodule of
+    // the repository
+    let empty = create_test_repo();
+    let empty_url = format!("file://{}", empty.path().display());
+    let repo = create_test_repo();
+    let before = snapshot(repo.path());
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "add-package",
+            "-r",
+            repo.path().to_str().unwrap(),
+            &empty_url,
+            "--path",
+            "vendor/empty",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No PKGBUILD found in vendor/empty"));
+    assert_eq!(snapshot(repo.path()), before);
+}
+
+/// Lists every file and directory under `dir`, including `.git`, with the
+/// content of the config files a submodule touches
+fn snapshot(dir: &Path) -> Vec<String> {
+    let mut entries = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current).unwrap() {
+            let path = entry.unwrap().path();
+            let relative = path
+                .strip_prefix(dir)
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            if path.is_dir() {
+                pending.push(path);
+            } else if relative == ".git/config" || relative == ".gitmodules" {
+                entries.push(format!(
+                    "{}:\n{}",
+                    relative,
+                    fs::read_to_string(&path).unwrap()
+                ));
+                continue;
+            }
+            entries.push(relative);
+        }
+    }
+    entries.sort();
+    entries
+}
+
+#[test]
+fn test_remove_package() {
+    let repo = create_test_repo();
+    for name in ["pkg-a", "pkg-b"] {
+        let pkg_dir = repo.path().join("packages").join(name);
+        fs::create_dir_all(&pkg_dir).unwrap();
+        fs::write(
+            pkg_dir.join("PKGBUILD"),
+            format!("pkgname={}\npkgver=1.0.0\npkgrel=1\n", name),
+        )
+        .unwrap();
+    }
+    let upstream = create_test_repo();
+    fs::write(
+        upstream.path().join("PKGBUILD"),
+        "pkgbase=foo\npkgname=(foo foo-docs)\npkgver=1.0.0\npkgrel=1\n",
+    )
+    .unwrap();
+    commit_all(upstream.path(), "Split package");
+    git(
+        repo.path(),
+        &[
+            "submodule",
+            "add",
+            "--name",
+            "foo",
+            upstream.path().to_str().unwrap(),
+            "packages/foo",
+        ],
+    );
+    commit_all(repo.path(), "Add packages");
+
+    let remove = |name: &str| {
+        Command::new("cargo")
+            .args([
+                "run",
+                "--",
+                "remove-package",
+                "-r",
+                repo.path().to_str().unwrap(),
+                name,
+            ])
+            .output()
+            .unwrap()
+    };
+
+    // Every trace of the submodule is gone and the removal is staged
+    let output = remove("foo");
+    assert!(
+        output.status.success(),
+        "remove-package failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        "foo\nfoo-docs"
+    );
+    assert_eq!(
+        git(repo.path(), &["status", "--porcelain"]),
+        "D  .gitmodules\nD  packages/foo"
+    );
+    assert!(!repo.path().join("packages/foo").exists());
+    assert!(!repo.path().join(".git/modules").exists());
+    let config = fs::read_to_string(repo.path().join(".git/config")).unwrap();
+    assert!(!config.contains("submodule"), "{}", config);
+    commit_all(repo.path(), "Remove foo");
+
+    // A submodule that is not checked out is found through .gitmodules
+    git(
+        repo.path(),
+        &[
+            "submodule",
+            "add",
+            upstream.path().to_str().unwrap(),
+            "vendor/bar",
+        ],
+    );
+    commit_all(repo.path(), "Add bar");
+    git(repo.path(), &["submodule", "deinit", "-q", "vendor/bar"]);
+    let output = remove("bar");
+    assert!(
+        output.status.success(),
+        "remove-package failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(repo.path(), &["status", "--porcelain"]),
+        "D  .gitmodules\nD  vendor/bar"
+    );
+    assert!(!repo.path().join("vendor/bar").exists());
+    assert!(!repo.path().join(".git/modules/packages/bar").exists());
+    commit_all(repo.path(), "Remove bar");
+
+    let output = remove("pkg-a");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "pkg-a");
+    assert_eq!(
+        git(repo.path(), &["status", "--porcelain"]),
+        "D  packages/pkg-a/PKGBUILD"
+    );
+    assert_eq!(list_packages(repo.path(), &[]), "pkg-b");
+
+    let output = remove("pkg-a");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Package not found: pkg-a"));
+}
+
+#[test]
+fn test_update_submodules() {
+    let niri = create_test_repo();
+    fs::write(
+        niri.path().join("PKGBUILD"),
+        "pkgname=niri\npkgver=25.01\npkgrel=1\n",
+    )
+    .unwrap();
+    let niri_pin = commit_all(niri.path(), "Release 25.01");
+
+    // ly tracks its stable branch, while development continues elsewhere
+    let ly = create_test_repo();
+    fs::write(
+        ly.path().join("PKGBUILD"),
+        "pkgname=ly\npkgver=1.0\npkgrel=1\n",
+    )
+    .unwrap();
+    let ly_pin = commit_all(ly.path(), "Release 1.0");
+    git(ly.path(), &["branch", "stable"]);
+
+    let repo = create_test_repo();
+    for (name, upstream) in [("niri", &niri), ("ly", &ly)] {
+        git(
+            repo.path(),
+            &[
+                "submodule",
+                "add",
+                "--name",
+                name,
+                upstream.path().to_str().unwrap(),
+                &format!("packages/{}", name),
+            ],
+        );
+    }
+    git(
+        repo.path(),
+        &[
+            "config",
+            "-f",
+            ".gitmodules",
+            "submodule.ly.branch",
+            "stable",
+        ],
+    );
+    commit_all(repo.path(), "Add packages");
+
+    fs::write(
+        niri.path().join("PKGBUILD"),
+        "pkgname=niri\npkgver=25.02\npkgrel=1\n",
+    )
+    .unwrap();
+    let niri_new = commit_all(niri.path(), "Release 25.02");
+    fs::write(
+        ly.path().join("PKGBUILD"),
+        "pkgname=ly\npkgver=2.0-dev\npkgrel=1\n",
+    )
+    .unwrap();
+    commit_all(ly.path(), "Start 2.0");
+
+    let update = |args: &[&str]| {
+        let output = Command::new("cargo")
+            .args([
+                "run",
+                "--",
+                "update-submodules",
+                "-r",
+                repo.path().to_str().unwrap(),
+            ])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "update-submodules failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    };
+
+    let branch = git(niri.path(), &["branch", "--show-current"]);
+    assert_eq!(
+        update(&[]),
+        format!(
+            "ly: up to date with origin/stable ({})\n\
+             niri: {}..{} (origin/{})\n  niri: 25.01-1 -> 25.02-1",
+            &ly_pin[..7],
+            &niri_pin[..7],
+            &niri_new[..7],
+            branch
+        )
+    );
+    assert_eq!(
+        git(repo.path(), &["status", "--porcelain"]),
+        "M  packages/niri"
+    );
+    git(repo.path(), &["reset", "-q", "--hard"]);
+    git(repo.path(), &["submodule", "-q", "update"]);
+
+    // One commit per package
+    git(ly.path(), &["checkout", "-q", "stable"]);
+    fs::write(
+        ly.path().join("PKGBUILD"),
+        "pkgname=ly\npkgver=1.1\npkgrel=1\n",
+    )
+    .unwrap();
+    commit_all(ly.path(), "Release 1.1");
+
+    let output = update(&["--commit", "niri", "ly"]);
+    assert!(output.contains("ly: 1.0-1 -> 1.1-1"), "{}", output);
+    assert_eq!(
+        git(repo.path(), &["log", "--format=%s", "-2"]),
+        "Update niri to 25.02-1\nUpdate ly to 1.1-1"
+    );
+    assert_eq!(git(repo.path(), &["status", "--porcelain"]), "");
+    assert_eq!(update(&["niri"]).lines().count(), 1);
+
+    // `branch = .` follows the superproject's current branch
+    git(
+        repo.path(),
+        &["config", "-f", ".gitmodules", "submodule.niri.branch", "."],
+    );
+    git(repo.path(), &["checkout", "-q", "-b", "next"]);
+    commit_all(repo.path(), "Track the superproject's branch");
+    git(niri.path(), &["checkout", "-q", "-b", "next"]);
+    fs::write(
+        niri.path().join("PKGBUILD"),
+        "pkgname=niri\npkgver=25.03\npkgrel=1\n",
+    )
+    .unwrap();
+    let niri_next = commit_all(niri.path(), "Release 25.03");
+    assert_eq!(
+        update(&["niri"]),
+        format!(
+            "niri: {}..{} (origin/next)\n  niri: 25.02-1 -> 25.03-1",
+            &niri_new[..7],
+            &niri_next[..7]
+        )
+    );
+
+    git(repo.path(), &["checkout", "-q", "--detach"]);
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "update-submodules",
+            "-r",
+            repo.path().to_str().unwrap(),
+            "niri",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("`branch = .` follows the superproject's branch, but HEAD is detached"));
+}
+
+#[test]
+fn test_package_groups_and_filters() {
+    let ly = create_test_repo();
+    fs::write(
+        ly.path().join("PKGBUILD"),
+        "pkgname=ly\npkgver=1.0\npkgrel=1\n",
+    )
+    .unwrap();
+    commit_all(ly.path(), "Release 1.0");
+
+    let repo = create_test_repo();
+    fs::write(
+        repo.path().join("syspac.toml"),
+        "[groups]\ncore = [\"bar\", \"ly*\"]\n",
+    )
+    .unwrap();
+    for (name, groups) in [("foo", "groups=('desktop')\n"), ("bar", "")] {
+        let dir = repo.path().join("packages").join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("PKGBUILD"),
+            format!("pkgname={}\npkgver=1.0\npkgrel=1\n{}", name, groups),
+        )
+        .unwrap();
+    }
+    git(
+        repo.path(),
+        &[
+            "submodule",
+            "add",
+            "--name",
+            "ly",
+            ly.path().to_str().unwrap(),
+            "packages/ly",
+        ],
+    );
+    commit_all(repo.path(), "Add packages");
+
+    assert_eq!(list_packages(repo.path(), &["--group", "desktop"]), "foo");
+    assert_eq!(list_packages(repo.path(), &["--group", "core"]), "bar\nly");
+    assert_eq!(
+        list_packages(repo.path(), &["--group", "core", "--group", "desktop"]),
+        "bar\nfoo\nly"
+    );
+    assert_eq!(list_packages(repo.path(), &["--include", "?a*"]), "bar");
+    assert_eq!(
+        list_packages(repo.path(), &["--exclude", "foo", "--exclude", "bar"]),
+        "ly"
+    );
+    assert_eq!(list_packages(repo.path(), &["--only-submodules"]), "ly");
+    assert_eq!(
+        list_packages(repo.path(), &["--only-direct", "--group", "core"]),
+        "bar"
+    );
+
+    // The same filters apply to detect-changes
+    assert_eq!(
+        detect_changes(repo.path(), &["--all", "--group", "core"]),
+        "bar ly"
+    );
+    fs::write(
+        repo.path().join("packages/foo/PKGBUILD"),
+        "pkgname=foo\npkgver=1.1\npkgrel=1\ngroups=('desktop')\n",
+    )
+    .unwrap();
+    fs::write(
+        repo.path().join("packages/bar/PKGBUILD"),
+        "pkgname=bar\npkgver=1.1\npkgrel=1\n",
+    )
+    .unwrap();
+    commit_all(repo.path(), "Update foo and bar");
+    assert_eq!(detect_changes(repo.path(), &[]), "bar foo");
+    assert_eq!(detect_changes(repo.path(), &["--exclude", "b*"]), "foo");
+    assert_eq!(detect_changes(repo.path(), &["--only-submodules"]), "");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "list-packages",
+            "-r",
+            repo.path().to_str().unwrap(),
+            "--group",
+            "nonexistent",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown group: nonexistent"));
+}
+
+#[test]
+fn test_package_settings() {
+    let repo = create_test_repo();
+    fs::write(
+        repo.path().join("syspac.toml"),
+        "[packages.settings.bar]\nskip_build = true\n",
+    )
+    .unwrap();
+    for name in ["foo", "bar", "baz"] {
+        let dir = repo.path().join("packages").join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("PKGBUILD"),
+            format!("pkgname={}\npkgver=1.0\npkgrel=1\n", name),
+        )
+        .unwrap();
+    }
+    // A package's own file; bar's root config section overrides the keys it sets
+    fs::write(
+        repo.path().join("packages/foo/syspac.toml"),
+        "skip_build = true\nmaintainers = [\"alice\"]\n",
+    )
+    .unwrap();
+    fs::write(
+        repo.path().join("packages/bar/syspac.toml"),
+        "skip_build = false\nmaintainers = [\"carol\"]\n",
+    )
+    .unwrap();
+    commit_all(repo.path(), "Add packages");
+
+    assert_eq!(detect_changes(repo.path(), &["--all"]), "baz");
+    assert_eq!(
+        detect_changes(repo.path(), &["--all", "--explain"]),
+        "baz (packages/baz)\n  reason: forced by --all\n\
+         bar (skipped by skip_build setting)\nfoo (skipped by skip_build setting)"
+    );
+    // Listing is unaffected
+    assert_eq!(list_packages(repo.path(), &[]), "bar\nbaz\nfoo");
+
+    let json: serde_json::Value =
+        serde_json::from_str(&list_packages(repo.path(), &["--format", "json"])).unwrap();
+    assert_eq!(json[0]["name"], "bar");
+    assert_eq!(json[0]["version"], "1.0-1");
+    assert_eq!(json[0]["settings"]["skip_build"], true);
+    assert_eq!(json[0]["settings"]["maintainers"][0], "carol");
+    assert_eq!(json[2]["settings"]["maintainers"][0], "alice");
+
+    fs::write(
+        repo.path().join("packages/baz/syspac.toml"),
+        "timeout_minutes = 0\n",
+    )
+    .unwrap();
+    let output = Command::new("cargo")
+        .args(["run", "--", "list-packages", "-r"])
+        .arg(repo.path())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Invalid packages/baz/syspac.toml"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_package_version_does_not_execute_pkgbuild() {
+    let dir = TempDir::new().unwrap();
+    let marker = dir.path().join("executed");
+    fs::write(
+        dir.path().join("PKGBUILD"),
+        format!(
+            "pkgname=foo\n_major=2\npkgver=${{_major}}.1\npkgrel=1\n\
+             touch '{marker}'\n_date=$(touch '{marker}')\n",
+            marker = marker.display()
+        ),
+    )
+    .unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "package-version"])
+        .arg(dir.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "2.1-1");
+    assert!(!marker.exists());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("line 5: command `touch` is not executed"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("line 6: _date: command substitution cannot be evaluated statically"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_info() {
+    let repo = create_test_repo();
+    let pkg_dir = repo.path().join("packages").join("foo");
+    fs::create_dir_all(&pkg_dir).unwrap();
+    fs::write(
+        pkg_dir.join("PKGBUILD"),
+        r#"pkgbase=foo
+pkgname=(foo foo-docs)
+pkgver=1.0
+pkgrel=2
+pkgdesc="Foo tool"
+arch=(x86_64)
+depends=(glibc)
+source=("https://example.com/foo-$pkgver.tar.gz")
+sha256sums=(SKIP)
+source_x86_64=(foo-bin.tar.gz)
+"#,
+    )
+    .unwrap();
+    commit_all(repo.path(), "Add foo");
+
+    let info = |args: &[&str]| {
+        let output = Command::new("cargo")
+            .args(["run", "--", "info", "-r", repo.path().to_str().unwrap()])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "info failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // By package name, by one of its pkgnames, or by path
+    let json: serde_json::Value =
+        serde_json::from_str(&info(&["foo", "--format", "json"])).unwrap();
+    assert_eq!(json["pkgname"], serde_json::json!(["foo", "foo-docs"]));
+    assert_eq!(json["pkgver"], "1.0");
+    assert_eq!(json["epoch"], serde_json::Value::Null);
+    assert_eq!(json["depends"], serde_json::json!(["glibc"]));
+    assert_eq!(
+        json["source"],
+        serde_json::json!(["https://example.com/foo-1.0.tar.gz"])
+    );
+    assert_eq!(json["sha256sums"], serde_json::json!(["SKIP"]));
+    assert_eq!(json["source_x86_64"], serde_json::json!(["foo-bin.tar.gz"]));
+    assert_eq!(
+        info(&["foo-docs", "--format", "json"]),
+        info(&[pkg_dir.to_str().unwrap(), "--format", "json"])
+    );
+
+    let text = info(&["foo"]);
+    assert!(text.contains("pkgdesc: Foo tool\n"), "{}", text);
+    assert!(text.contains("pkgname: foo foo-docs\n"), "{}", text);
+    assert!(!text.contains("makedepends"), "{}", text);
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "info",
+            "-r",
+            repo.path().to_str().unwrap(),
+            "bar",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown package: bar"));
+}